@@ -0,0 +1,126 @@
+// src/ansi.rs
+
+// --------------------------------------------------------------------------------
+// Removing terminal escape sequences (colors, cursor movement, window titles,
+// hyperlinks, ...) from output, for logs that read as plain text.
+// --------------------------------------------------------------------------------
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// What to do with escape sequences in the output we store
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnsiMode {
+    /// Store them as printed, colors and all
+    #[default]
+    Keep,
+    /// Store plain text only
+    Strip,
+    /// Store both: the log as printed, and a plain-text copy next to it
+    Both,
+}
+
+/// Where in an escape sequence the stripper is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum State {
+    /// Plain text
+    #[default]
+    Ground,
+    /// Just saw ESC
+    Escape,
+    /// ESC followed by intermediate bytes, e.g. "ESC (" before a charset
+    Intermediate,
+    /// Control sequence: "ESC [" params... final byte (colors, cursor moves)
+    Csi,
+    /// Operating system command: "ESC ]" ... BEL or ST (titles, hyperlinks)
+    Osc,
+    /// DCS, SOS, PM or APC string, running up to ST
+    Str,
+    /// ESC inside an OSC or other string, which may be the start of ST ("ESC \")
+    StrEscape,
+}
+
+/// Strips escape sequences from a stream of output. Keeps its state between
+/// calls, so a sequence split across two chunks is still removed whole.
+#[derive(Default)]
+pub struct Stripper {
+    state: State,
+}
+
+impl Stripper {
+    /// `input` without the escape sequences in it (or begun/ended by it)
+    pub fn strip(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len());
+        for &b in input {
+            self.state = match (self.state, b) {
+                (State::Ground, 0x1b) => State::Escape,
+                (State::Ground, _) => {
+                    out.push(b);
+                    State::Ground
+                }
+
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']') => State::Osc,
+                (State::Escape, b'P' | b'X' | b'^' | b'_') => State::Str,
+                (State::Escape, 0x1b) => State::Escape,
+                (State::Escape | State::Intermediate, 0x20..=0x2f) => State::Intermediate,
+                // The final byte of a two-byte sequence, e.g. "ESC 7" (save cursor)
+                (State::Escape | State::Intermediate, _) => State::Ground,
+
+                (State::Csi, 0x1b) => State::Escape,
+                (State::Csi, 0x40..=0x7e) => State::Ground,
+                (State::Csi, _) => State::Csi,
+
+                (State::Osc, 0x07) => State::Ground,
+                // An OSC never spans lines; most likely it was never terminated,
+                // and we don't want to eat the rest of the log
+                (State::Osc, b'\n') => {
+                    out.push(b);
+                    State::Ground
+                }
+                (State::Osc | State::Str, 0x1b) => State::StrEscape,
+                (State::Osc, _) => State::Osc,
+                (State::Str, _) => State::Str,
+
+                (State::StrEscape, b'\\') => State::Ground,
+                (State::StrEscape, _) => State::Str,
+            };
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strip `chunks` one after the other, as the tee-threads would
+    fn strip(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut stripper = Stripper::default();
+        chunks.iter().flat_map(|chunk| stripper.strip(chunk)).collect()
+    }
+
+    #[test]
+    fn whole_sequences() {
+        assert_eq!(strip(&[b"\x1b[1;31mred\x1b[0m plain"]), b"red plain");
+        assert_eq!(strip(&[b"\x1b]0;title\x07text"]), b"text");
+        assert_eq!(strip(&[b"\x1b(Bcharset \x1b7saved"]), b"charset saved");
+    }
+
+    #[test]
+    fn sequences_split_across_chunks() {
+        assert_eq!(strip(&[b"a\x1b", b"[31mb"]), b"ab");
+        assert_eq!(strip(&[b"a\x1b[3", b"1", b"mb\x1b[0", b"m"]), b"ab");
+        // An OSC hyperlink ended by ST, split between its ESC and '\'
+        assert_eq!(
+            strip(&[b"\x1b]8;;http://x\x1b", b"\\link\x1b]8;;\x1b\\"]),
+            b"link"
+        );
+        assert_eq!(strip(&[b"\x1bP", b"dcs", b" data\x1b", b"\\after"]), b"after");
+    }
+
+    #[test]
+    fn unterminated_osc_stops_at_newline() {
+        assert_eq!(strip(&[b"\x1b]0;never ended", b"\nnext line"]), b"\nnext line");
+    }
+}
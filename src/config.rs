@@ -0,0 +1,1062 @@
+// src/config.rs
+
+// --------------------------------------------------------------------------------
+// Settings, from the command line and from stash.toml. Both are the same `Config`
+// struct, so every option can be given either way: what's passed on the command
+// line wins over the file, which wins over the built-in default.
+//
+// Files come in layers, each over the one before:
+//   1. ~/.config/stash/stash.toml, the user's own
+//   2. `.stash.toml` files of the project we're in, from the repository root
+//      down to the current directory (once approved with `stash trust`)
+// --------------------------------------------------------------------------------
+use chrono::Duration;
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ansi::AnsiMode,
+    ignore::{IgnoreEntry, IgnoreList},
+    logfile::{Compression, LogFormat},
+    redact::{self, RedactConfig},
+    rotate::{BucketBy, Limits, Retention},
+    timeout,
+    trust, units,
+};
+
+/// Where logs go unless told otherwise
+pub const DEFAULT_LOG_DIR: &str = "~/.cache/stash";
+
+/// How many runs to keep per bucket unless told otherwise
+pub const DEFAULT_RETAIN: usize = 20;
+
+/// The name of project config files
+pub const PROJECT_FILE: &str = ".stash.toml";
+
+/// The lists and tables a file can `replace`, rather than add to, those of
+/// the layers below it
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Replace {
+    Ignore,
+    LookThrough,
+    RetainFor,
+    Groups,
+    Redact,
+    Profile,
+}
+
+impl Replace {
+    /// The setting it's about
+    pub fn key(self) -> &'static str {
+        match self {
+            Replace::Ignore => "ignore",
+            Replace::LookThrough => "look_through",
+            Replace::RetainFor => "retain_for",
+            Replace::Groups => "groups",
+            Replace::Redact => "redact",
+            Replace::Profile => "profile",
+        }
+    }
+}
+
+/// Every setting, as given at one level (the command line, or stash.toml).
+/// `None` means "not set here".
+#[derive(Args, Deserialize, Debug, Default, Clone)]
+pub struct Config {
+    /// Where to store rolling logs of past commands (default: ~/.cache/stash)
+    #[clap(long, global = true, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Max number of log files to retain per program (see --bucket-by) (default: 20)
+    #[clap(long, value_name = "N")]
+    pub retain: Option<usize>,
+
+    /// Count `--retain` per `program` (default), or over `global`ly all runs
+    #[clap(long, value_enum, value_name = "HOW")]
+    pub bucket_by: Option<BucketBy>,
+
+    /// Per-program (or per-group) overrides of `retain`, e.g. `cargo = 100`
+    #[clap(skip)]
+    pub retain_for: Option<HashMap<String, usize>>,
+
+    /// Named groups of programs that share one retention bucket,
+    /// e.g. `build = ["cargo", "make"]`
+    #[clap(skip)]
+    pub groups: Option<HashMap<String, Vec<String>>>,
+
+    /// Delete logs of runs that started longer ago than this, whatever their
+    /// number (e.g. 12h, 14d, 2w)
+    #[clap(long, value_name = "AGE", value_parser = units::parse_duration)]
+    #[serde(default, deserialize_with = "units::deserialize_duration")]
+    pub max_age: Option<Duration>,
+
+    /// Keep this many failed runs per program, counted apart from successful
+    /// ones (which keep using --retain)
+    #[clap(long, value_name = "N")]
+    pub retain_failed: Option<usize>,
+
+    /// Let failed runs get this old, instead of --max-age
+    #[clap(long, value_name = "AGE", value_parser = units::parse_duration)]
+    #[serde(default, deserialize_with = "units::deserialize_duration")]
+    pub max_age_failed: Option<Duration>,
+
+    /// Delete the log as soon as the command exits successfully: only keep failures
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub discard_success: Option<bool>,
+
+    /// Pin this run, so it's never pruned (see `stash pin`)
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub pin: Option<bool>,
+
+    /// Delete the oldest logs until all of them together fit in this size
+    /// (e.g. 500M, 2GiB)
+    #[clap(long, value_name = "SIZE", value_parser = units::parse_size)]
+    #[serde(default, deserialize_with = "units::deserialize_size")]
+    pub max_total: Option<u64>,
+
+    /// Stop logging a run's output after this much; the terminal still gets
+    /// all of it (e.g. 100MiB)
+    #[clap(long, value_name = "SIZE", value_parser = units::parse_size)]
+    #[serde(default, deserialize_with = "units::deserialize_size")]
+    pub max_run_size: Option<u64>,
+
+    /// Print which logs were pruned, and why, to stderr
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub report_pruned: Option<bool>,
+
+    /// Add to the list of commands we ignore (e.g. tui apps)
+    /// (space separated; names, globs, `re:` regexes or paths)
+    #[clap(long, value_name = "PROG", num_args = 1.., value_parser = IgnoreEntry::from_arg)]
+    pub ignore: Option<Vec<IgnoreEntry>>,
+
+    /// Wrapper commands to look through when matching `ignore`, so that e.g.
+    /// `sudo vim` counts as vim
+    #[clap(long, value_name = "PROG", num_args = 1..)]
+    pub look_through: Option<Vec<String>>,
+
+    /// Run the command on a pseudo-terminal so it keeps colors, progress bars
+    /// and prompts (stdout and stderr are then logged as one stream)
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", overrides_with = "no_pty")]
+    pub pty: Option<bool>,
+
+    /// Stop the command once it's run this long (e.g. 90s, 15m): SIGTERM to
+    /// its process group, then SIGKILL after --kill-after. stash then exits
+    /// with 124.
+    #[clap(long, value_name = "AGE", value_parser = units::parse_nonzero_duration)]
+    #[serde(default, deserialize_with = "units::deserialize_nonzero_duration")]
+    pub timeout: Option<Duration>,
+
+    /// How long a timed-out command gets to stop after SIGTERM (default: 10s)
+    #[clap(long, value_name = "AGE", value_parser = units::parse_nonzero_duration)]
+    #[serde(default, deserialize_with = "units::deserialize_nonzero_duration")]
+    pub kill_after: Option<Duration>,
+
+    /// With --timeout, leave the command in the foreground, so it can read
+    /// from the terminal and be stopped with Ctrl-Z. Only the command itself
+    /// is timed out then, not what it started.
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub foreground: Option<bool>,
+
+    /// Also log what's typed into the command, as a stream of its own (see
+    /// `show --stdin`). Without --pty, this makes the log jsonl.
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub capture_stdin: Option<bool>,
+
+    /// With --capture-stdin, log input typed while the terminal doesn't echo
+    /// it (passwords) as "[REDACTED]" (default: true)
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub redact_unechoed: Option<bool>,
+
+    /// How to store the output: `raw` bytes, or `jsonl` records that keep
+    /// per-chunk timestamps and stdout/stderr apart (default: raw)
+    #[clap(long, value_enum)]
+    pub format: Option<LogFormat>,
+
+    /// Compress the log as it's written (default: none). `stash show` and
+    /// `stash grep` read compressed logs as they are.
+    #[clap(long, value_enum, value_name = "HOW")]
+    pub compress: Option<Compression>,
+
+    /// What to do with colors and other escape sequences in the log: `keep`
+    /// them (default), `strip` them, or store `both` (plus a plain-text copy)
+    #[clap(long, value_enum, value_name = "HOW")]
+    pub ansi: Option<AnsiMode>,
+
+    /// Secrets to blank out of the logs: `presets` and/or regex `patterns`
+    #[clap(skip)]
+    pub redact: Option<RedactConfig>,
+
+    /// `[profile.<program>]` tables: settings for runs of one program, on top
+    /// of the rest of the file
+    #[clap(skip)]
+    pub profile: Option<HashMap<String, Config>>,
+
+    /// Lists and tables of this layer that replace those of the layers below,
+    /// e.g. `replace = ["ignore"]`
+    #[clap(skip)]
+    pub replace: Option<Vec<Replace>>,
+}
+
+impl Config {
+    /// Layer `self` over `lower`: settings made here win, the rest come from
+    /// `lower`. Lists are extended and tables merged, unless `self` says to
+    /// `replace` them.
+    pub fn or(self, mut lower: Config) -> Config {
+        for key in self.replace.iter().flatten() {
+            match key {
+                Replace::Ignore => lower.ignore = None,
+                Replace::LookThrough => lower.look_through = None,
+                Replace::RetainFor => lower.retain_for = None,
+                Replace::Groups => lower.groups = None,
+                Replace::Redact => lower.redact = None,
+                Replace::Profile => lower.profile = None,
+            }
+        }
+        Config {
+            log_dir: self.log_dir.or(lower.log_dir),
+            retain: self.retain.or(lower.retain),
+            bucket_by: self.bucket_by.or(lower.bucket_by),
+            retain_for: merge_tables(lower.retain_for, self.retain_for),
+            groups: merge_tables(lower.groups, self.groups),
+            max_age: self.max_age.or(lower.max_age),
+            retain_failed: self.retain_failed.or(lower.retain_failed),
+            max_age_failed: self.max_age_failed.or(lower.max_age_failed),
+            discard_success: self.discard_success.or(lower.discard_success),
+            pin: self.pin.or(lower.pin),
+            max_total: self.max_total.or(lower.max_total),
+            max_run_size: self.max_run_size.or(lower.max_run_size),
+            report_pruned: self.report_pruned.or(lower.report_pruned),
+            ignore: extend(lower.ignore, self.ignore),
+            look_through: extend(lower.look_through, self.look_through),
+            pty: self.pty.or(lower.pty),
+            timeout: self.timeout.or(lower.timeout),
+            kill_after: self.kill_after.or(lower.kill_after),
+            foreground: self.foreground.or(lower.foreground),
+            capture_stdin: self.capture_stdin.or(lower.capture_stdin),
+            redact_unechoed: self.redact_unechoed.or(lower.redact_unechoed),
+            format: self.format.or(lower.format),
+            compress: self.compress.or(lower.compress),
+            ansi: self.ansi.or(lower.ansi),
+            redact: match (self.redact, lower.redact) {
+                (Some(upper), Some(lower)) => Some(RedactConfig {
+                    presets: extend(Some(lower.presets), Some(upper.presets)).unwrap(),
+                    patterns: extend(Some(lower.patterns), Some(upper.patterns)).unwrap(),
+                    replacement: upper.replacement.or(lower.replacement),
+                }),
+                (upper, lower) => upper.or(lower),
+            },
+            profile: merge_tables(lower.profile, self.profile),
+            // Already done with
+            replace: None,
+        }
+    }
+
+    /// This config with the profile for the program `prog` (if there is one)
+    /// layered on top
+    pub fn with_profile(self, prog: &str) -> Config {
+        // 1. The profile for this program, by name however it was invoked
+        let name = prog.rsplit('/').next().unwrap_or(prog);
+        let Some(mut profile) = self.profile.as_ref().and_then(|p| p.get(name)).cloned() else {
+            return self;
+        };
+        debug!("using [profile.{}]", name);
+
+        // 2. Every run may prune any program's logs, so a profile's retention
+        //    limits have to hold on all of them: they stay in the profiles for
+        //    `retention` to give the program's bucket, rather than layered on
+        //    top where they'd apply to every program
+        profile.retain = None;
+        profile.max_age = None;
+        profile.retain_failed = None;
+        profile.max_age_failed = None;
+        profile.or(self)
+    }
+
+    /// The settings for running `prog`: `cli` over `files`, with the program's
+    /// profile in between. A retention flag on the command line also takes
+    /// the place of the program's own limit (see `limits_for`), which would
+    /// otherwise outrank it.
+    pub fn for_command(cli: Config, files: Config, prog: &str) -> Config {
+        let mut files = files.with_profile(prog);
+        if cli.retain.is_some()
+            || cli.max_age.is_some()
+            || cli.retain_failed.is_some()
+            || cli.max_age_failed.is_some()
+        {
+            let name = prog.rsplit('/').next().unwrap_or(prog);
+            let profiles = files.profile.get_or_insert_with(HashMap::new);
+            let profile = profiles.entry(name.to_string()).or_default();
+            profile.retain = cli.retain.or(profile.retain);
+            profile.max_age = cli.max_age.or(profile.max_age);
+            profile.retain_failed = cli.retain_failed.or(profile.retain_failed);
+            profile.max_age_failed = cli.max_age_failed.or(profile.max_age_failed);
+        }
+        cli.or(files)
+    }
+
+    /// The built-in defaults, of the settings that have one
+    pub fn defaults() -> Config {
+        Config {
+            log_dir: Some(PathBuf::from(DEFAULT_LOG_DIR)),
+            retain: Some(DEFAULT_RETAIN),
+            bucket_by: Some(BucketBy::default()),
+            discard_success: Some(false),
+            pin: Some(false),
+            report_pruned: Some(false),
+            pty: Some(false),
+            kill_after: Some(timeout::DEFAULT_KILL_AFTER),
+            foreground: Some(false),
+            capture_stdin: Some(false),
+            redact_unechoed: Some(true),
+            format: Some(LogFormat::default()),
+            compress: Some(Compression::default()),
+            ansi: Some(AnsiMode::default()),
+            ..Config::default()
+        }
+    }
+
+    /// Every setting by name, in the order they're documented, with its value
+    /// written the way it would be in stash.toml (if it's set)
+    pub fn settings(&self) -> Vec<(&'static str, Option<String>)> {
+        fn name<E: ValueEnum>(value: &E) -> String {
+            format!("{:?}", value.to_possible_value().unwrap().get_name())
+        }
+        fn list<T: fmt::Display>(items: &[T]) -> String {
+            let items: Vec<String> = items.iter().map(T::to_string).collect();
+            format!("[{}]", items.join(", "))
+        }
+        fn table<V>(map: &HashMap<String, V>, render: impl Fn(&V) -> String) -> String {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let entries: Vec<String> = keys
+                .into_iter()
+                .map(|k| format!("{} = {}", toml_key(k), render(&map[k])))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        let quoted = |s: &String| format!("{s:?}");
+
+        vec![
+            (
+                "log_dir",
+                self.log_dir
+                    .as_ref()
+                    .map(|d| format!("{:?}", d.display().to_string())),
+            ),
+            ("retain", self.retain.map(|n| n.to_string())),
+            ("bucket_by", self.bucket_by.as_ref().map(name)),
+            (
+                "retain_for",
+                self.retain_for.as_ref().map(|t| table(t, usize::to_string)),
+            ),
+            (
+                "groups",
+                self.groups.as_ref().map(|t| {
+                    table(t, |members| {
+                        list(&members.iter().map(quoted).collect::<Vec<_>>())
+                    })
+                }),
+            ),
+            (
+                "max_age",
+                self.max_age.map(|d| format!("{:?}", units::format_age(d))),
+            ),
+            ("retain_failed", self.retain_failed.map(|n| n.to_string())),
+            (
+                "max_age_failed",
+                self.max_age_failed
+                    .map(|d| format!("{:?}", units::format_age(d))),
+            ),
+            (
+                "discard_success",
+                self.discard_success.map(|b| b.to_string()),
+            ),
+            ("pin", self.pin.map(|b| b.to_string())),
+            (
+                "max_total",
+                self.max_total
+                    .map(|n| format!("{:?}", units::format_exact_size(n))),
+            ),
+            (
+                "max_run_size",
+                self.max_run_size
+                    .map(|n| format!("{:?}", units::format_exact_size(n))),
+            ),
+            ("report_pruned", self.report_pruned.map(|b| b.to_string())),
+            ("ignore", self.ignore.as_deref().map(list)),
+            (
+                "look_through",
+                self.look_through
+                    .as_ref()
+                    .map(|l| list(&l.iter().map(quoted).collect::<Vec<_>>())),
+            ),
+            ("pty", self.pty.map(|b| b.to_string())),
+            ("timeout", self.timeout.map(|d| format!("{:?}", units::format_age(d)))),
+            ("kill_after", self.kill_after.map(|d| format!("{:?}", units::format_age(d)))),
+            ("foreground", self.foreground.map(|b| b.to_string())),
+            ("capture_stdin", self.capture_stdin.map(|b| b.to_string())),
+            (
+                "redact_unechoed",
+                self.redact_unechoed.map(|b| b.to_string()),
+            ),
+            ("format", self.format.as_ref().map(name)),
+            ("compress", self.compress.as_ref().map(name)),
+            ("ansi", self.ansi.as_ref().map(name)),
+            (
+                "redact",
+                self.redact.as_ref().map(|r| {
+                    let mut parts = Vec::new();
+                    if !r.presets.is_empty() {
+                        parts.push(format!(
+                            "presets = {}",
+                            list(&r.presets.iter().map(quoted).collect::<Vec<_>>())
+                        ));
+                    }
+                    if !r.patterns.is_empty() {
+                        parts.push(format!(
+                            "patterns = {}",
+                            list(&r.patterns.iter().map(quoted).collect::<Vec<_>>())
+                        ));
+                    }
+                    if let Some(replacement) = &r.replacement {
+                        parts.push(format!("replacement = {replacement:?}"));
+                    }
+                    format!("{{ {} }}", parts.join(", "))
+                }),
+            ),
+            (
+                "profile",
+                self.profile.as_ref().map(|t| {
+                    table(t, |profile| {
+                        let set: Vec<String> = profile
+                            .settings()
+                            .into_iter()
+                            .filter_map(|(key, value)| Some(format!("{key} = {}", value?)))
+                            .collect();
+                        format!("{{ {} }}", set.join(", "))
+                    })
+                }),
+            ),
+        ]
+    }
+
+    /// The log directory, with `~` and `$VARS` expanded
+    pub fn log_dir(&self) -> PathBuf {
+        let dir = self
+            .log_dir
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_LOG_DIR));
+        expand_path(dir)
+    }
+
+    pub fn retain(&self) -> usize {
+        self.retain.unwrap_or(DEFAULT_RETAIN)
+    }
+
+    /// The format logs are written in. Input captured through a pipe would be
+    /// mixed in with the output in a raw log, with no telling them apart, so
+    /// it's logged in jsonl then.
+    pub fn log_format(&self) -> LogFormat {
+        match self.format.unwrap_or_default() {
+            LogFormat::Raw if self.capture_stdin == Some(true) && self.pty != Some(true) => {
+                LogFormat::Jsonl
+            }
+            format => format,
+        }
+    }
+
+    /// How much history to keep
+    pub fn retention(&self) -> Retention {
+        Retention {
+            retain: self.retain(),
+            bucket_by: self.bucket_by.unwrap_or_default(),
+            limits_for: self.limits_for(),
+            groups: self.groups.clone().unwrap_or_default(),
+            max_age: self.max_age,
+            retain_failed: self.retain_failed,
+            max_age_failed: self.max_age_failed,
+            max_total: self.max_total,
+        }
+    }
+
+    /// The buckets with retention limits of their own: those in `retain_for`,
+    /// and the programs whose profile sets any
+    fn limits_for(&self) -> HashMap<String, Limits> {
+        let mut limits_for: HashMap<String, Limits> = HashMap::new();
+        for (name, retain) in self.retain_for.iter().flatten() {
+            limits_for.entry(name.clone()).or_default().retain = Some(*retain);
+        }
+        for (name, profile) in self.profile.iter().flatten() {
+            let own = Limits {
+                retain: profile.retain,
+                max_age: profile.max_age,
+                retain_failed: profile.retain_failed,
+                max_age_failed: profile.max_age_failed,
+            };
+            if own.retain.is_none()
+                && own.max_age.is_none()
+                && own.retain_failed.is_none()
+                && own.max_age_failed.is_none()
+            {
+                continue;
+            }
+            let limits = limits_for.entry(name.clone()).or_default();
+            limits.retain = own.retain.or(limits.retain);
+            limits.max_age = own.max_age;
+            limits.retain_failed = own.retain_failed;
+            limits.max_age_failed = own.max_age_failed;
+        }
+        limits_for
+    }
+
+    /// The commands not to log
+    pub fn ignore_list(&self) -> io::Result<IgnoreList> {
+        let entries = self.ignore.as_deref().unwrap_or_default();
+        IgnoreList::new(entries, self.look_through.clone().unwrap_or_default())
+    }
+}
+
+/// A table key, quoted unless it's a bare key
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    }
+}
+
+/// `lower` followed by `upper`, if there's either
+fn extend<T>(lower: Option<Vec<T>>, upper: Option<Vec<T>>) -> Option<Vec<T>> {
+    match (lower, upper) {
+        (Some(mut lower), Some(upper)) => {
+            lower.extend(upper);
+            Some(lower)
+        }
+        (lower, upper) => lower.or(upper),
+    }
+}
+
+/// The entries of both, those of `upper` winning
+fn merge_tables<V>(
+    lower: Option<HashMap<String, V>>,
+    upper: Option<HashMap<String, V>>,
+) -> Option<HashMap<String, V>> {
+    match (lower, upper) {
+        (Some(mut lower), Some(upper)) => {
+            lower.extend(upper);
+            Some(lower)
+        }
+        (lower, upper) => lower.or(upper),
+    }
+}
+
+/// One config file's worth of settings
+pub struct Layer {
+    pub path: PathBuf,
+    pub config: Config,
+}
+
+/// Something wrong with a config file
+pub struct Problem {
+    pub message: String,
+    /// What we do about it
+    pub fallback: Fallback,
+}
+
+/// How we carry on despite a problem in a config file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    /// Leave out the one setting (or entry) that's wrong
+    SkipSetting,
+    /// Leave out the whole file
+    SkipFile,
+    /// Run the commands it applies to without logging them: better than
+    /// logging the secrets a broken redaction rule was meant to catch
+    Unlogged,
+}
+
+impl Problem {
+    fn new(message: String, fallback: Fallback) -> Problem {
+        Problem { message, fallback }
+    }
+}
+
+/// The config files that apply in the current directory, and what's wrong
+/// with them
+#[derive(Default)]
+pub struct Loaded {
+    /// The files we could use, lowest layer first
+    pub layers: Vec<Layer>,
+    /// Project files we may not use, not being approved (as they are now)
+    pub untrusted: Vec<PathBuf>,
+    pub problems: Vec<Problem>,
+}
+
+impl Loaded {
+    /// Tell about files left out, and problems with the others. With `strict`
+    /// they're errors, not to be ignored.
+    pub fn warn(&self, strict: bool) {
+        for path in &self.untrusted {
+            eprintln!(
+                "stash: ignoring {} until you approve it with `stash trust`",
+                path.display()
+            );
+        }
+        for problem in &self.problems {
+            match (strict, problem.fallback) {
+                (false, Fallback::SkipFile) => {
+                    eprintln!("stash: {} (using none of its settings)", problem.message)
+                }
+                (false, Fallback::SkipSetting) => eprintln!("stash: {} (ignored)", problem.message),
+                (false, Fallback::Unlogged) => eprintln!(
+                    "stash: {} (commands it applies to run without being logged)",
+                    problem.message
+                ),
+                (true, _) => eprintln!("stash: {}", problem.message),
+            }
+        }
+    }
+
+    /// All the layers, merged
+    pub fn merged(&self) -> Config {
+        self.layers.iter().fold(Config::default(), |config, layer| {
+            layer.config.clone().or(config)
+        })
+    }
+
+    /// Profiles whose retention limits can't apply, because their program's
+    /// runs count towards a group, or towards the one bucket of all runs
+    pub fn unused_profile_limits(&self) -> Vec<String> {
+        let retention = self.merged().retention();
+        let mut unused = Vec::new();
+        for layer in &self.layers {
+            let mut profiles: Vec<_> = layer.config.profile.iter().flatten().collect();
+            profiles.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, profile) in profiles {
+                let set: Vec<&str> = [
+                    ("retain", profile.retain.is_some()),
+                    ("max_age", profile.max_age.is_some()),
+                    ("retain_failed", profile.retain_failed.is_some()),
+                    ("max_age_failed", profile.max_age_failed.is_some()),
+                ]
+                .into_iter()
+                .filter_map(|(key, set)| set.then_some(key))
+                .collect();
+                let why = match retention.bucket_of(name) {
+                    _ if set.is_empty() => continue,
+                    bucket if bucket == name => continue,
+                    "" => "all runs count towards one bucket (bucket_by = \"global\")".to_string(),
+                    group => format!("{name}'s runs count towards the group {group}"),
+                };
+                unused.push(format!(
+                    "{}: `profile.{}` sets {}, which has no effect: {why}",
+                    layer.path.display(),
+                    toml_key(name),
+                    set.join(", ")
+                ));
+            }
+        }
+        unused
+    }
+}
+
+/// Read every config file that applies in the current directory: `user_file`
+/// (or ~/.config/stash/stash.toml), then the project's. A file we can't make
+/// sense of is left out.
+pub fn load_layers(user_file: Option<&Path>) -> Loaded {
+    let mut loaded = Loaded::default();
+    let add = |loaded: &mut Loaded, path: PathBuf, contents: &str| {
+        let (parsed, problems) = parse_file(&path, contents);
+        loaded.problems.extend(problems);
+        if let Some(config) = parsed {
+            loaded.layers.push(Layer { path, config });
+        }
+    };
+
+    // 1. The user's stash.toml, unless we're given another one. That one has
+    //    to exist.
+    let (user_file, required) = self::user_file(user_file);
+    match fs::read_to_string(&user_file) {
+        Ok(contents) => add(&mut loaded, user_file, &contents),
+        Err(e) if required => loaded.problems.push(Problem::new(
+            format!("{}: {e}", user_file.display()),
+            Fallback::SkipFile,
+        )),
+        Err(_) => {}
+    }
+
+    // 2. The project's, as long as they've been approved
+    let cwd = env::current_dir().unwrap_or_default();
+    for path in project_files(&cwd) {
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if !trust::is_trusted(&path, &contents) {
+            loaded.untrusted.push(path);
+            continue;
+        }
+        debug!("reading project config {}", path.display());
+        add(&mut loaded, path, &contents);
+    }
+
+    // 3. Settings that only go wrong together
+    for message in loaded.unused_profile_limits() {
+        loaded
+            .problems
+            .push(Problem::new(message, Fallback::SkipSetting));
+    }
+    loaded
+}
+
+/// Load every config file that applies in the current directory (see
+/// `load_layers`), each layered over the one before.
+///
+/// Problems with a file (TOML errors, bad values, unknown keys) are reported,
+/// and it's left out if it can't be read. With `strict`, they're an error once
+/// all of them have been reported.
+pub fn load(user_file: Option<&Path>, strict: bool) -> io::Result<Config> {
+    let loaded = load_layers(user_file);
+    loaded.warn(strict);
+    if strict && !loaded.problems.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not running anything with a broken config (--strict-config)",
+        ));
+    }
+    Ok(loaded.merged())
+}
+
+/// The user's config file: `given`, or ~/.config/stash/stash.toml (or
+/// wherever $XDG_CONFIG_HOME says). Also whether it has to exist, which it
+/// does when given.
+pub fn user_file(given: Option<&Path>) -> (PathBuf, bool) {
+    match given {
+        Some(path) => (expand_path(path), true),
+        None => (default_user_file(), false),
+    }
+}
+
+/// ~/.config/stash/stash.toml, or wherever $XDG_CONFIG_HOME says
+pub fn default_user_file() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_default()
+        .join("stash")
+        .join("stash.toml")
+}
+
+/// The project files that apply in `dir`: a `.stash.toml` there or in any
+/// directory above it, up to the root of the repository it's in, outermost
+/// first. Outside of a repository only `dir` itself is looked at.
+pub fn project_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for ancestor in dir.ancestors() {
+        let file = ancestor.join(PROJECT_FILE);
+        if file.is_file() {
+            files.push(file);
+        }
+        if ancestor.join(".git").exists() {
+            files.reverse();
+            return files;
+        }
+    }
+    // Never found the repository root
+    files.retain(|file| file.parent() == Some(dir));
+    files
+}
+
+/// Parse the config file at `path`, which holds `contents`: its settings,
+/// unless it's broken, and what's wrong with it. A relative `log_dir` in it is
+/// taken to be relative to the file's directory.
+pub fn parse_file(path: &Path, contents: &str) -> (Option<Config>, Vec<Problem>) {
+    let mut problems = Vec::new();
+    let in_file = |message: String, fallback| {
+        Problem::new(format!("{}: {message}", path.display()), fallback)
+    };
+    let parsed: Result<Config, _> =
+        serde_ignored::deserialize(toml::Deserializer::new(contents), |key| {
+            // e.g. "profile.?.cargo.retian", where the "?" is an Option
+            let key = key.to_string().replace("?.", "");
+            problems.push(in_file(format!("unknown key `{key}`"), Fallback::SkipSetting))
+        });
+    match parsed {
+        Ok(mut config) => {
+            config.resolve_log_dirs(path.parent().unwrap_or(Path::new(".")));
+            let mut dropped = config.drop_global_only_in_profiles();
+            dropped.extend(config.drop_invalid_ignores());
+            for message in dropped {
+                problems.push(in_file(message, Fallback::SkipSetting));
+            }
+            for message in config.invalid_redaction() {
+                problems.push(in_file(message, Fallback::Unlogged));
+            }
+            (Some(config), problems)
+        }
+        Err(e) => {
+            // Point at where it went wrong, like a compiler would
+            let at = match e.span() {
+                Some(span) => {
+                    let before = &contents[..span.start];
+                    let line = before.matches('\n').count() + 1;
+                    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+                    format!(":{line}:{column}")
+                }
+                None => String::new(),
+            };
+            let message = e.message().trim_end().replace('\n', ", ");
+            let problem = Problem::new(
+                format!("{}{at}: {message}", path.display()),
+                Fallback::SkipFile,
+            );
+            (None, vec![problem])
+        }
+    }
+}
+
+impl Config {
+    /// Leave out the settings of profiles that would change how every
+    /// program's logs are kept, not just the profile's; returns what was left out
+    fn drop_global_only_in_profiles(&mut self) -> Vec<String> {
+        let mut dropped = Vec::new();
+        let mut profiles: Vec<_> = self.profile.iter_mut().flatten().collect();
+        profiles.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, profile) in profiles {
+            let set = [
+                ("bucket_by", profile.bucket_by.take().is_some()),
+                ("max_total", profile.max_total.take().is_some()),
+                ("groups", profile.groups.take().is_some()),
+            ];
+            for (key, _) in set.iter().filter(|(_, set)| *set) {
+                dropped.push(format!(
+                    "`profile.{}.{key}` would apply to all programs, so it can't be set in a profile",
+                    toml_key(name)
+                ));
+            }
+        }
+        dropped
+    }
+
+    /// Leave out the ignore entries (here and in the profiles) whose patterns
+    /// don't compile, rather than have them stop every run; returns why
+    fn drop_invalid_ignores(&mut self) -> Vec<String> {
+        let mut dropped = Vec::new();
+        if let Some(entries) = &mut self.ignore {
+            entries.retain(|entry| match entry.check() {
+                Ok(()) => true,
+                Err(e) => {
+                    dropped.push(e);
+                    false
+                }
+            });
+        }
+        for profile in self.profile.iter_mut().flat_map(|p| p.values_mut()) {
+            dropped.extend(profile.drop_invalid_ignores());
+        }
+        dropped
+    }
+
+    /// What's wrong with the redaction rules (here and in the profiles). They
+    /// stay as they are: a run they apply to fails to compile them, and goes
+    /// unlogged.
+    fn invalid_redaction(&self) -> Vec<String> {
+        let mut invalid = Vec::new();
+        if let Some(Err(e)) = self.redact.as_ref().map(redact::Rules::new) {
+            invalid.push(e.to_string());
+        }
+        for profile in self.profile.iter().flat_map(|p| p.values()) {
+            invalid.extend(profile.invalid_redaction());
+        }
+        invalid
+    }
+
+    /// Expand `log_dir` (and those of the profiles), and make it absolute by
+    /// putting `base` in front if it's relative
+    fn resolve_log_dirs(&mut self, base: &Path) {
+        if let Some(dir) = &self.log_dir {
+            self.log_dir = Some(base.join(expand_path(dir)));
+        }
+        for profile in self.profile.iter_mut().flat_map(|p| p.values_mut()) {
+            profile.resolve_log_dirs(base);
+        }
+    }
+}
+
+/// Expand a leading `~` to the home directory, and `$VAR` / `${VAR}` to the
+/// value of environment variables. Unset variables are left as they are.
+pub fn expand_path(path: &Path) -> PathBuf {
+    let Some(text) = path.to_str() else {
+        return path.to_path_buf();
+    };
+
+    // 1. "~" or "~/..." (but not "~user")
+    let mut out = String::new();
+    let mut rest = text;
+    if rest == "~" || rest.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            out.push_str(&home.to_string_lossy());
+            rest = &rest[1..];
+        }
+    }
+
+    // 2. Environment variables
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match env::var(name) {
+            Ok(value) if !name.is_empty() => out.push_str(&value),
+            _ => out.push_str(&rest[i..i + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    PathBuf::from(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn upper_layer_wins_and_extends_lists() {
+        let lower = parse(
+            r#"
+            retain = 10
+            max_age = "7d"
+            look_through = ["nice"]
+            retain_for = { cargo = 3, make = 4 }
+            "#,
+        );
+        let upper = parse(
+            r#"
+            retain = 5
+            look_through = ["time"]
+            retain_for = { make = 2 }
+            "#,
+        );
+        let merged = upper.or(lower);
+
+        assert_eq!(merged.retain, Some(5));
+        assert_eq!(merged.max_age, Some(Duration::days(7)));
+        assert_eq!(merged.look_through.unwrap(), ["nice", "time"]);
+        let retain_for = merged.retain_for.unwrap();
+        assert_eq!((retain_for["cargo"], retain_for["make"]), (3, 2));
+    }
+
+    #[test]
+    fn replace_drops_the_lower_layers_entries() {
+        let lower = parse(
+            r#"
+            look_through = ["nice"]
+            retain_for = { cargo = 3 }
+            "#,
+        );
+        let upper = parse(
+            r#"
+            replace = ["look_through", "retain_for"]
+            look_through = ["time"]
+            "#,
+        );
+        let merged = upper.or(lower);
+
+        assert_eq!(merged.look_through.unwrap(), ["time"]);
+        assert!(merged.retain_for.is_none());
+    }
+
+    #[test]
+    fn project_files_stop_at_the_repository_root() {
+        let root = env::temp_dir().join(format!("stash-test-{}-project", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let repo = root.join("repo");
+        let deep = repo.join("src/bin");
+        fs::create_dir_all(&deep).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+        for dir in [&root, &repo, &deep] {
+            fs::write(dir.join(PROJECT_FILE), "").unwrap();
+        }
+
+        // From the root of the repository down; the one above it doesn't count
+        assert_eq!(
+            project_files(&deep),
+            [repo.join(PROJECT_FILE), deep.join(PROJECT_FILE)]
+        );
+        assert_eq!(project_files(&repo.join("src")), [repo.join(PROJECT_FILE)]);
+
+        // Outside of a repository, only the directory itself
+        fs::remove_dir(repo.join(".git")).unwrap();
+        assert_eq!(project_files(&deep), [deep.join(PROJECT_FILE)]);
+        assert!(project_files(&repo.join("src")).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn problems_point_at_line_and_column() {
+        let problem = |contents: &str| {
+            let (config, problems) = parse_file(Path::new("stash.toml"), contents);
+            assert!(config.is_none());
+            assert_eq!(problems.len(), 1);
+            assert_eq!(problems[0].fallback, Fallback::SkipFile);
+            problems[0].message.clone()
+        };
+
+        // A bad value, pointed at from its opening quote
+        let message = problem("retain = 3\nmax_age = \"14x\"\n");
+        assert!(message.starts_with("stash.toml:2:11: unknown unit 'x'"), "{message}");
+
+        // Broken TOML, one line long
+        let message = problem("retain = 3\n[profile.cargo\n");
+        assert!(message.starts_with("stash.toml:2:15: invalid table header"), "{message}");
+        assert!(!message.contains('\n'), "{message}");
+    }
+
+    #[test]
+    fn captured_input_gets_a_jsonl_log() {
+        let format = |toml: &str| parse(toml).log_format();
+
+        assert_eq!(format("capture_stdin = true"), LogFormat::Jsonl);
+        assert_eq!(format("capture_stdin = true\nformat = \"raw\""), LogFormat::Jsonl);
+        assert_eq!(format("capture_stdin = true\npty = true"), LogFormat::Raw);
+        assert_eq!(format("capture_stdin = false"), LogFormat::Raw);
+    }
+
+    #[test]
+    fn paths_are_expanded() {
+        let home = dirs::home_dir().unwrap();
+        env::set_var("STASH_TEST_EXPAND", "/srv");
+        env::remove_var("STASH_TEST_UNSET");
+        let expand = |path: &str| expand_path(Path::new(path));
+
+        assert_eq!(expand("~"), home);
+        assert_eq!(expand("~/logs"), home.join("logs"));
+        assert_eq!(expand("~alice/logs"), Path::new("~alice/logs"));
+        assert_eq!(expand("$STASH_TEST_EXPAND/logs"), Path::new("/srv/logs"));
+        assert_eq!(expand("${STASH_TEST_EXPAND}logs"), Path::new("/srvlogs"));
+        assert_eq!(expand("$STASH_TEST_UNSET/logs"), Path::new("$STASH_TEST_UNSET/logs"));
+        assert_eq!(expand("${STASH_TEST_EXPAND/logs"), Path::new("${STASH_TEST_EXPAND/logs"));
+        assert_eq!(expand("costs$5"), Path::new("costs$5"));
+    }
+}
@@ -0,0 +1,322 @@
+// src/config_cmd.rs
+
+// --------------------------------------------------------------------------------
+// `stash config`: what the configuration adds up to, and why.
+//   show      every setting, and which layer it comes from
+//   check     look for mistakes in the config files
+//   explain   what stash would do with a given command
+// --------------------------------------------------------------------------------
+use chrono::Local;
+use clap::{Args, Subcommand, ValueEnum};
+use std::{env, fs, io, path::Path};
+
+use crate::{
+    ansi::AnsiMode,
+    config::{self, Config},
+    ignore::IgnoreEntry,
+    logfile,
+    redact::Rules,
+    store, timeout, trust, units,
+};
+
+/// Settings that add up across layers (unless a layer says to `replace` them),
+/// rather than the highest layer's winning
+const MERGED: &[&str] = &[
+    "ignore",
+    "look_through",
+    "retain_for",
+    "groups",
+    "redact",
+    "profile",
+];
+
+/// How wide the `key = value` column of `show` is, before the provenance
+const VALUE_WIDTH: usize = 40;
+
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[clap(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration, and where each setting comes from
+    Show,
+
+    /// Check the config files (including projects' not yet approved) for mistakes
+    Check,
+
+    /// Tell what stash would do with a command: whether it's logged, with
+    /// which profile, and where to
+    Explain {
+        /// The command, as it would be given to stash
+        #[clap(required = true, last = true)]
+        cmd: Vec<String>,
+    },
+}
+
+/// Run `stash config ...`, with the settings `cli` made on the command line
+/// and `user_file` in place of the usual stash.toml. Returns false when
+/// `check` found problems.
+pub fn run(args: &ConfigArgs, cli: &Config, user_file: Option<&Path>) -> io::Result<bool> {
+    match &args.command {
+        ConfigCommand::Show => show(cli, user_file),
+        ConfigCommand::Check => check(user_file),
+        ConfigCommand::Explain { cmd } => explain(cmd, cli, user_file),
+    }
+}
+
+fn show(cli: &Config, user_file: Option<&Path>) -> io::Result<bool> {
+    // 1. Every layer, lowest first
+    let loaded = config::load_layers(user_file);
+    loaded.warn(false);
+    let mut layers: Vec<(String, &Config)> = loaded
+        .layers
+        .iter()
+        .map(|layer| (layer.path.display().to_string(), &layer.config))
+        .collect();
+    layers.push(("command line".to_string(), cli));
+    println!("# Layers, lowest first:");
+    for (source, _) in &layers {
+        println!("#   {source}");
+    }
+
+    // 2. What they add up to, and which of them each setting comes from
+    let effective = cli.clone().or(loaded.merged());
+    let defaults = Config::defaults().settings();
+    for ((key, value), (_, default)) in effective.settings().into_iter().zip(defaults) {
+        let (line, from) = match (value, default) {
+            (Some(value), _) => (format!("{key} = {value}"), sources(key, &layers).join(", ")),
+            (None, Some(default)) => (format!("{key} = {default}"), "default".to_string()),
+            (None, None) => (format!("# {key}"), "not set".to_string()),
+        };
+        println!("{line:<VALUE_WIDTH$}  # {from}");
+    }
+    Ok(true)
+}
+
+/// The layers `key` gets its value from: the highest that sets it, or for
+/// settings that add up, all of them since the last that replaced it
+fn sources<'a>(key: &str, layers: &'a [(String, &Config)]) -> Vec<&'a str> {
+    let mut from = Vec::new();
+    for (source, config) in layers {
+        if config.replace.iter().flatten().any(|r| r.key() == key) {
+            from.clear();
+        }
+        if is_set(config, key) {
+            from.push(source.as_str());
+        }
+    }
+    if !MERGED.contains(&key) {
+        from.drain(..from.len().saturating_sub(1));
+    }
+    from
+}
+
+fn is_set(config: &Config, key: &str) -> bool {
+    config
+        .settings()
+        .into_iter()
+        .any(|(k, value)| k == key && value.is_some())
+}
+
+fn check(user_file: Option<&Path>) -> io::Result<bool> {
+    // 1. The user's file, and every project file that applies here, approved or not
+    let together = config::load_layers(user_file).unused_profile_limits();
+    let (user_file, required) = config::user_file(user_file);
+    let mut files = Vec::new();
+    if required || user_file.exists() {
+        files.push(user_file);
+    }
+    files.extend(config::project_files(&env::current_dir()?));
+    if files.is_empty() {
+        println!("no config files (defaults only)");
+        return Ok(true);
+    }
+
+    // 2. Parse each (which also tries out patterns and redaction rules)
+    let mut all_ok = true;
+    for path in files {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("{}: {e}", path.display());
+                all_ok = false;
+                continue;
+            }
+        };
+        let (_, problems) = config::parse_file(&path, &contents);
+        for problem in &problems {
+            println!("{}", problem.message);
+        }
+        all_ok &= problems.is_empty();
+
+        let is_project = path.file_name().is_some_and(|n| n == config::PROJECT_FILE);
+        match (
+            problems.is_empty(),
+            is_project && !trust::is_trusted(&path, &contents),
+        ) {
+            (true, false) => println!("{}: ok", path.display()),
+            (true, true) => println!(
+                "{}: ok, but not used until you approve it with `stash trust`",
+                path.display()
+            ),
+            (false, _) => {}
+        }
+    }
+
+    // 3. Then the settings that only go wrong together
+    for problem in together {
+        println!("{problem}");
+        all_ok = false;
+    }
+    Ok(all_ok)
+}
+
+fn explain(cmd: &[String], cli: &Config, user_file: Option<&Path>) -> io::Result<bool> {
+    // 1. Settle the settings, as a run of `cmd` would
+    let loaded = config::load_layers(user_file);
+    loaded.warn(false);
+    let prog = &cmd[0];
+    let name = prog.rsplit('/').next().unwrap_or(prog);
+    let cfg = Config::for_command(cli.clone(), loaded.merged(), prog);
+    println!("command:    {}", cmd.join(" "));
+
+    // 2. Which profile
+    let profile_from: Vec<String> = loaded
+        .layers
+        .iter()
+        .filter(|layer| {
+            layer
+                .config
+                .profile
+                .as_ref()
+                .is_some_and(|p| p.contains_key(name))
+        })
+        .map(|layer| layer.path.display().to_string())
+        .collect();
+    if profile_from.is_empty() {
+        println!("profile:    none");
+    } else {
+        println!(
+            "profile:    [profile.{name}], from {}",
+            profile_from.join(", ")
+        );
+    }
+
+    // 3. Whether it's logged at all
+    if let Some(entry) = cfg.ignore_list()?.matching(cmd) {
+        let from = loaded
+            .layers
+            .iter()
+            .map(|layer| (layer.path.display().to_string(), &layer.config))
+            .chain([("command line".to_string(), cli)])
+            .rev()
+            .find(|(_, config)| {
+                let entries = config.ignore.iter().flatten();
+                entries
+                    .chain(profile_ignores(config, name))
+                    .any(|e| e.to_string() == entry.to_string())
+            })
+            .map(|(source, _)| source)
+            .unwrap_or_default();
+        println!("logged:     no, it matches ignore entry {entry} (from {from}) and is run as is");
+        return Ok(true);
+    }
+    if let Err(e) = Rules::new(&cfg.redact.clone().unwrap_or_default()) {
+        println!("logged:     no, its redaction rules are broken ({e}), so it's run as is");
+        return Ok(true);
+    }
+    println!("logged:     yes");
+
+    // 4. Where to, and how
+    let log_dir = cfg.log_dir();
+    let format = cfg.log_format();
+    let compression = cfg.compress.unwrap_or_default();
+    let ansi = cfg.ansi.unwrap_or_default();
+    let id = Local::now().format(store::ID_FORMAT).to_string();
+    let log = log_dir.join(logfile::file_name(&id, format, compression));
+    println!("log file:   {}", log.display());
+    if ansi == AnsiMode::Both {
+        println!("            plus {}", logfile::plain_copy(&log).display());
+    }
+    println!(
+        "stored as:  format {}, compress {}, ansi {}, {}",
+        value_name(&format),
+        value_name(&compression),
+        value_name(&ansi),
+        if cfg.pty.unwrap_or(false) {
+            "on a pty"
+        } else {
+            "through pipes"
+        },
+    );
+    match &cfg.redact {
+        Some(redact) if !redact.presets.is_empty() || !redact.patterns.is_empty() => println!(
+            "redaction:  presets [{}], {} pattern(s) of our own",
+            redact.presets.join(", "),
+            redact.patterns.len()
+        ),
+        _ => println!("redaction:  none"),
+    }
+    if let Some(limit) = cfg.timeout {
+        let kill_after = cfg.kill_after.unwrap_or(timeout::DEFAULT_KILL_AFTER);
+        let to = if cfg.foreground.unwrap_or(false) {
+            "the command"
+        } else {
+            "its process group"
+        };
+        println!(
+            "timeout:    SIGTERM to {} after {}, SIGKILL {} later",
+            to,
+            units::format_age(limit),
+            units::format_age(kill_after)
+        );
+    }
+
+    // 5. How long it's kept
+    let retention = cfg.retention();
+    let bucket = retention.bucket_of(name);
+    let of = match bucket {
+        "" => "of all programs together".to_string(),
+        b if b == name => format!("of {name}"),
+        group => format!("of the group {group}"),
+    };
+    let mut kept = format!("the newest {} runs {of}", retention.cap(bucket, false));
+    if retention.retain_failed(bucket).is_some() {
+        kept += &format!(", and {} failed ones", retention.cap(bucket, true));
+    }
+    let (max_age, max_age_failed) = (
+        retention.max_age(bucket, false),
+        retention.max_age(bucket, true),
+    );
+    if let Some(age) = max_age {
+        kept += &format!(", none older than {}", units::format_age(age));
+    }
+    if let Some(age) = max_age_failed.filter(|_| max_age_failed != max_age) {
+        kept += &format!(" ({} when failed)", units::format_age(age));
+    }
+    if cfg.pin.unwrap_or(false) {
+        kept = "for good (pinned)".to_string();
+    } else if cfg.discard_success.unwrap_or(false) {
+        kept += "; only if it fails";
+    }
+    println!("kept:       {kept}");
+    Ok(true)
+}
+
+/// The ignore entries of the profile for `name` in `config`
+fn profile_ignores<'a>(config: &'a Config, name: &str) -> impl Iterator<Item = &'a IgnoreEntry> {
+    config
+        .profile
+        .as_ref()
+        .and_then(|p| p.get(name))
+        .and_then(|p| p.ignore.as_ref())
+        .into_iter()
+        .flatten()
+}
+
+fn value_name<E: ValueEnum>(value: &E) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
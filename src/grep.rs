@@ -0,0 +1,262 @@
+// src/grep.rs
+
+// --------------------------------------------------------------------------------
+// `stash grep`: search every retained log for a regex.
+//
+// Matches are grouped per run, newest run first, under a header naming the run:
+//   == 20250712-153045.123  [101]  cargo test
+//   1042:thread 'parse::empty' panicked at src/parse.rs:88:9
+// --------------------------------------------------------------------------------
+use clap::Args;
+use regex::bytes::{Regex, RegexBuilder};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    logfile::{self, LogReader},
+    store::{self, Run, RunFilter},
+};
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    /// Regular expression to look for (Rust `regex` syntax)
+    #[clap(value_name = "PATTERN")]
+    pattern: String,
+
+    #[clap(flatten)]
+    filter: RunFilter,
+
+    /// Match case-insensitively
+    #[clap(short, long)]
+    ignore_case: bool,
+
+    /// Lines of context to show before and after each match
+    #[clap(short = 'C', long, value_name = "N")]
+    context: Option<usize>,
+
+    /// Lines of context to show before each match
+    #[clap(short = 'B', long, value_name = "N")]
+    before_context: Option<usize>,
+
+    /// Lines of context to show after each match
+    #[clap(short = 'A', long, value_name = "N")]
+    after_context: Option<usize>,
+
+    /// Only print how many lines matched in each run
+    #[clap(short, long)]
+    count: bool,
+}
+
+/// Search the logs; returns whether anything matched (like grep's exit status)
+pub fn run(log_dir: &Path, args: &GrepArgs) -> io::Result<bool> {
+    // 1. Compile the pattern up front so a typo is reported before any work
+    let re = RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let before = args.before_context.or(args.context).unwrap_or(0);
+    let after = args.after_context.or(args.context).unwrap_or(0);
+
+    // 2. Search each matching run, newest first
+    let mut out = io::stdout().lock();
+    let mut found = false;
+    for run in store::runs(log_dir)?.iter().rev() {
+        if !args.filter.matches(run) {
+            continue;
+        }
+        let mut search = Search {
+            re: &re,
+            run,
+            count_only: args.count,
+            before,
+            after,
+            line_no: 0,
+            matches: 0,
+            recent: VecDeque::new(),
+            after_left: 0,
+            last_printed: None,
+        };
+        search.run(&mut out)?;
+        found |= search.matches > 0;
+    }
+    out.flush()?;
+    Ok(found)
+}
+
+/// The state of searching one run's log line by line
+struct Search<'a> {
+    re: &'a Regex,
+    run: &'a Run,
+    count_only: bool,
+    before: usize,
+    after: usize,
+    /// Number of the line being looked at (1-based)
+    line_no: usize,
+    /// Matching lines seen so far
+    matches: usize,
+    /// The last `before` lines, in case the next one matches
+    recent: VecDeque<(usize, Vec<u8>)>,
+    /// How many more lines to print as trailing context
+    after_left: usize,
+    /// Number of the last line printed, to know when to print a "--" separator
+    last_printed: Option<usize>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        // 1. Split the log into lines as the chunks come in. Structured logs
+        //    are searched as the plain output they describe, and runs with a
+        //    plain-text copy (`--ansi both`) by that, free of escape sequences.
+        let plain_copy = logfile::plain_copy(&self.run.log);
+        let log = if plain_copy.exists() {
+            &plain_copy
+        } else {
+            &self.run.log
+        };
+        let mut reader = LogReader::open(log)?;
+        let mut partial = Vec::new();
+        while let Some(chunk) = reader.next_chunk()? {
+            partial.extend_from_slice(&chunk.data);
+            let mut start = 0;
+            while let Some(i) = partial[start..].iter().position(|&b| b == b'\n') {
+                let end = start + i;
+                self.line(&partial[start..end], out)?;
+                start = end + 1;
+            }
+            partial.drain(..start);
+        }
+        if !partial.is_empty() {
+            self.line(&partial, out)?;
+        }
+
+        // 2. In count mode, the whole result is one line per run
+        if self.count_only && self.matches > 0 {
+            writeln!(
+                out,
+                "{}  {}  {}",
+                self.run.id,
+                self.matches,
+                self.describe_command()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Look at the next line of the log
+    fn line(&mut self, line: &[u8], out: &mut dyn Write) -> io::Result<()> {
+        self.line_no += 1;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if self.re.is_match(line) {
+            self.matches += 1;
+            if self.count_only {
+                return Ok(());
+            }
+            if self.matches == 1 {
+                writeln!(out, "== {}  {}", self.run.id, self.describe_command())?;
+            }
+            // Leading context, then the match itself
+            let recent = std::mem::take(&mut self.recent);
+            for (no, text) in recent {
+                self.print(no, b'-', &text, out)?;
+            }
+            self.print(self.line_no, b':', line, out)?;
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            self.after_left -= 1;
+            self.print(self.line_no, b'-', line, out)?;
+        } else if self.before > 0 && !self.count_only {
+            self.recent.push_back((self.line_no, line.to_vec()));
+            if self.recent.len() > self.before {
+                self.recent.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, no: usize, sep: u8, text: &[u8], out: &mut dyn Write) -> io::Result<()> {
+        // Like grep, only between groups of context
+        let context = self.before > 0 || self.after > 0;
+        if context && self.last_printed.is_some_and(|last| no > last + 1) {
+            writeln!(out, "--")?;
+        }
+        self.last_printed = Some(no);
+        write!(out, "{}{}", no, sep as char)?;
+        out.write_all(text)?;
+        out.write_all(b"\n")
+    }
+
+    /// "[101]  cargo test" for the run's header line
+    fn describe_command(&self) -> String {
+        match &self.run.meta {
+            Some(meta) => format!("[{}]  {}", meta.status_label(), meta.command_line()),
+            None => "[?]  ?".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// What `stash grep` prints for a log of `lines`, with this much context
+    fn grep(pattern: &str, before: usize, after: usize, lines: &[&str]) -> String {
+        let re = Regex::new(pattern).unwrap();
+        let run = Run {
+            id: "20250712-153045.123".to_string(),
+            log: PathBuf::from("20250712-153045.123.log"),
+            size: 0,
+            meta: None,
+        };
+        let mut search = Search {
+            re: &re,
+            run: &run,
+            count_only: false,
+            before,
+            after,
+            line_no: 0,
+            matches: 0,
+            recent: VecDeque::new(),
+            after_left: 0,
+            last_printed: None,
+        };
+        let mut out = Vec::new();
+        for line in lines {
+            search.line(line.as_bytes(), &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn context_around_matches() {
+        let lines = ["a", "hit 1", "b", "c", "d", "hit 2", "e", "hit 3\r", "f"];
+        assert_eq!(
+            grep("hit", 1, 1, &lines),
+            "== 20250712-153045.123  [?]  ?\n\
+             1-a\n2:hit 1\n3-b\n\
+             --\n\
+             5-d\n6:hit 2\n7-e\n8:hit 3\n9-f\n"
+        );
+        assert_eq!(
+            grep("hit", 2, 0, &lines),
+            "== 20250712-153045.123  [?]  ?\n\
+             1-a\n2:hit 1\n\
+             --\n\
+             4-c\n5-d\n6:hit 2\n7-e\n8:hit 3\n"
+        );
+    }
+
+    #[test]
+    fn no_separator_without_context() {
+        let lines = ["hit 1", "a", "hit 2", "hit 3"];
+        assert_eq!(
+            grep("hit", 0, 0, &lines),
+            "== 20250712-153045.123  [?]  ?\n1:hit 1\n3:hit 2\n4:hit 3\n"
+        );
+        assert_eq!(grep("miss", 1, 1, &lines), "");
+    }
+}
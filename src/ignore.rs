@@ -0,0 +1,451 @@
+// src/ignore.rs
+
+// --------------------------------------------------------------------------------
+// The ignore list: which commands are run without logging (TUI apps, pagers).
+//
+// Each entry is a pattern for the program:
+//   vim            its name, however it was invoked (/usr/bin/vim, ./vim, or a
+//                  symlink that resolves to vim)
+//   *top           a glob on that name (*, ?, [...])
+//   re:^n?vim$     a regex on that name
+//   /opt/games/*   a glob (or re:) containing a '/', matched against the full
+//                  path of the executable, as invoked or with symlinks resolved
+//
+// or, in stash.toml, a rule that only ignores some of a program's subcommands:
+//   { program = "git", args = ["log", "diff", "show"] }
+// --------------------------------------------------------------------------------
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    env, fmt, fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Options of well-known wrappers that take an argument, so we can skip over
+/// them to find the command being wrapped
+const WRAPPER_OPTIONS: &[(&str, &str)] = &[
+    (
+        "sudo",
+        "-u --user -g --group -C --close-from -D --chdir -h --host -p --prompt \
+         -r --role -t --type -U --other-user -T --command-timeout",
+    ),
+    ("doas", "-u -C"),
+    ("env", "-u --unset -C --chdir"),
+    ("nice", "-n --adjustment"),
+    ("ionice", "-c --class -n --classdata"),
+    ("time", "-f --format -o --output"),
+    ("stdbuf", "-i --input -o --output -e --error"),
+];
+
+/// Options of well-known programs with subcommands that take an argument, so
+/// that `git -C repo log` is found to run `log`, not `repo`. Other programs'
+/// options are taken not to.
+const SUBCOMMAND_OPTIONS: &[(&str, &str)] = &[
+    (
+        "git",
+        "-C -c --git-dir --work-tree --namespace --config-env --super-prefix",
+    ),
+    ("cargo", "-C --config -Z --color"),
+    (
+        "docker",
+        "-c --context -H --host -l --log-level --config --tlscacert --tlscert --tlskey",
+    ),
+    (
+        "kubectl",
+        "-n --namespace --context --cluster --user -s --server --kubeconfig \
+         --token --as --as-group --request-timeout -v",
+    ),
+    ("npm", "-w --workspace --prefix --userconfig --loglevel"),
+    ("systemctl", "-H --host -M --machine -t --type -p --property --state"),
+];
+
+/// One entry of `ignore` in stash.toml
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum IgnoreEntry {
+    /// Every run of the program
+    Program(String),
+    /// Runs of the program whose subcommand (the first argument that isn't
+    /// an option, or the value of one) matches one of `args`, or all of them
+    /// without any `args`
+    Rule {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl IgnoreEntry {
+    /// An `--ignore` on the command line, which always covers every run of
+    /// the program; checked right away so a bad pattern is a usage error
+    pub fn from_arg(arg: &str) -> Result<IgnoreEntry, String> {
+        Pattern::parse(arg)?;
+        Ok(IgnoreEntry::Program(arg.to_string()))
+    }
+
+    /// Whether the entry's patterns compile; the error says which doesn't
+    pub fn check(&self) -> Result<(), String> {
+        Rule::new(self.clone()).map(|_| ())
+    }
+}
+
+impl fmt::Display for IgnoreEntry {
+    /// As it would be written in stash.toml
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IgnoreEntry::Program(program) => write!(f, "{program:?}"),
+            IgnoreEntry::Rule { program, args } => {
+                write!(f, "{{ program = {program:?}, args = {args:?} }}")
+            }
+        }
+    }
+}
+
+/// A program name, glob, regex or path to match programs or arguments against
+#[derive(Debug)]
+struct Pattern {
+    /// Match against the executable's full path rather than its name
+    on_path: bool,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Name(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn parse(source: &str) -> Result<Pattern, String> {
+        let (kind, on_path) = if let Some(re) = source.strip_prefix("re:") {
+            let re = Regex::new(&format!("^(?:{re})$")).map_err(|e| invalid(source, e))?;
+            (Kind::Regex(re), source.contains('/'))
+        } else if source.contains(['*', '?', '[']) {
+            let re = Regex::new(&glob_to_regex(source)).map_err(|e| invalid(source, e))?;
+            (Kind::Regex(re), source.contains('/'))
+        } else if source.contains('/') {
+            (Kind::Name(source.to_string()), true)
+        } else {
+            (Kind::Name(source.to_string()), false)
+        };
+        Ok(Pattern { on_path, kind })
+    }
+
+    /// Does the program `prog` (as it appears in a command line, found at
+    /// the `paths` of `locate_exe`) match?
+    fn matches_program(&self, prog: &str, paths: &[String]) -> bool {
+        if self.on_path {
+            // Through a symlink (/usr/bin/vim -> /etc/alternatives/vim ->
+            // vim.basic), either end of it will do
+            if paths.is_empty() {
+                return self.is_match(prog);
+            }
+            return paths.iter().any(|path| self.is_match(path));
+        }
+        // By the name it was invoked as, or the name of what that resolves to
+        self.is_match(basename(prog)) || paths.iter().any(|path| self.is_match(basename(path)))
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match &self.kind {
+            Kind::Name(name) => name == text,
+            Kind::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+/// Why `source` isn't a valid pattern
+fn invalid(source: &str, e: regex::Error) -> String {
+    format!("invalid ignore pattern {source:?}: {}", regex_error(&e))
+}
+
+/// A regex error on one line: the regex crate's own message draws a caret
+/// under the problem, which means little in a warning
+pub fn regex_error(e: &regex::Error) -> String {
+    let e = e.to_string();
+    let reason = e.lines().last().unwrap_or_default();
+    reason.trim_start_matches("error: ").to_string()
+}
+
+/// An `IgnoreEntry`, compiled
+struct Rule {
+    entry: IgnoreEntry,
+    program: Pattern,
+    /// Subcommands to ignore; `None` for all of them
+    args: Option<Vec<Pattern>>,
+}
+
+impl Rule {
+    fn new(entry: IgnoreEntry) -> Result<Rule, String> {
+        let (program, args) = match &entry {
+            IgnoreEntry::Program(program) => (Pattern::parse(program)?, None),
+            IgnoreEntry::Rule { program, args } if args.is_empty() => {
+                (Pattern::parse(program)?, None)
+            }
+            IgnoreEntry::Rule { program, args } => {
+                let args = args
+                    .iter()
+                    .map(|a| Pattern::parse(a))
+                    .collect::<Result<_, _>>()?;
+                (Pattern::parse(program)?, Some(args))
+            }
+        };
+        Ok(Rule {
+            entry,
+            program,
+            args,
+        })
+    }
+
+    /// Does `cmd`, whose program was found at `paths`, match?
+    fn matches(&self, cmd: &[String], paths: &[String]) -> bool {
+        if !self.program.matches_program(&cmd[0], paths) {
+            return false;
+        }
+        let Some(args) = &self.args else {
+            return true;
+        };
+        subcommand(basename(&cmd[0]), &cmd[1..]).is_some_and(|sub| args.iter().any(|a| a.is_match(sub)))
+    }
+}
+
+/// The rules from stash.toml and `--ignore`, and the wrappers to look through
+pub struct IgnoreList {
+    rules: Vec<Rule>,
+    /// Wrapper commands like `sudo` or `env`: `sudo vim` counts as running vim
+    look_through: Vec<String>,
+}
+
+impl IgnoreList {
+    pub fn new(entries: &[IgnoreEntry], look_through: Vec<String>) -> io::Result<IgnoreList> {
+        let rules = entries
+            .iter()
+            .map(|e| Rule::new(e.clone()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(IgnoreList {
+            rules,
+            look_through,
+        })
+    }
+
+    /// The entry that makes us ignore `cmd`, if any. With wrappers to look
+    /// through, both the wrapper and the command it runs are checked.
+    pub fn matching(&self, cmd: &[String]) -> Option<&IgnoreEntry> {
+        let mut cmd = cmd;
+        while let Some(prog) = cmd.first() {
+            let paths = locate_exe(prog);
+            if let Some(rule) = self.rules.iter().find(|r| r.matches(cmd, &paths)) {
+                return Some(&rule.entry);
+            }
+            let name = basename(prog);
+            if !self.look_through.iter().any(|w| w == name) {
+                return None;
+            }
+            cmd = unwrap(name, &cmd[1..]);
+        }
+        None
+    }
+}
+
+/// The options of `program` in `table` that take an argument
+fn options_taking_arg(table: &[(&str, &'static str)], program: &str) -> Vec<&'static str> {
+    table
+        .iter()
+        .find(|(p, _)| *p == program)
+        .map_or(Vec::new(), |(_, opts)| opts.split_whitespace().collect())
+}
+
+/// How many of `args` the option at their start takes up: 2 if it's one of
+/// `takes_arg` (given as `-C dir`, not `-Cdir` or `--opt=value`), else 1
+fn option_len(takes_arg: &[&str], args: &[String]) -> usize {
+    let skip = if takes_arg.contains(&args[0].as_str()) { 2 } else { 1 };
+    skip.min(args.len())
+}
+
+/// The subcommand of `program` in its `args`: the first that isn't an option
+/// or the value of one (for the programs in `SUBCOMMAND_OPTIONS`)
+fn subcommand<'a>(program: &str, mut args: &'a [String]) -> Option<&'a String> {
+    let takes_arg = options_taking_arg(SUBCOMMAND_OPTIONS, program);
+    while let Some(arg) = args.first() {
+        if arg == "--" {
+            return args.get(1);
+        }
+        if !arg.starts_with('-') {
+            return Some(arg);
+        }
+        args = &args[option_len(&takes_arg, args)..];
+    }
+    None
+}
+
+/// Skip a wrapper's own options (and, for `env` and `sudo`, variable
+/// assignments) in `args`, leaving the command it runs
+fn unwrap<'a>(wrapper: &str, mut args: &'a [String]) -> &'a [String] {
+    let takes_arg = options_taking_arg(WRAPPER_OPTIONS, wrapper);
+    while let Some(arg) = args.first() {
+        if arg == "--" {
+            return &args[1..];
+        }
+        if arg.starts_with('-') {
+            args = &args[option_len(&takes_arg, args)..];
+        } else if arg.contains('=') && matches!(wrapper, "env" | "sudo") {
+            args = &args[1..];
+        } else {
+            break;
+        }
+    }
+    args
+}
+
+/// Where the program `prog` lives: found on $PATH (or relative to the current
+/// directory, if it has a '/' in it), as an absolute path, and then where
+/// that leads with symlinks resolved, if it's somewhere else
+fn locate_exe(prog: &str) -> Vec<String> {
+    let located = if prog.contains('/') {
+        env::current_dir().ok().map(|cwd| cwd.join(prog))
+    } else {
+        env::var_os("PATH").and_then(|path| {
+            env::split_paths(&path)
+                .map(|dir| dir.join(prog))
+                .find(|path| is_executable(path))
+        })
+    };
+    let Some(located) = located else {
+        return Vec::new();
+    };
+    // Tidy up "./" and the like, as canonicalize() would
+    let located: PathBuf = located.components().collect();
+    let mut paths = vec![located.to_string_lossy().into_owned()];
+    if let Ok(resolved) = fs::canonicalize(&located) {
+        if resolved != located {
+            paths.push(resolved.to_string_lossy().into_owned());
+        }
+    }
+    paths
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Turn a shell glob into an anchored regex: `*` and `?` don't cross a '/',
+/// `[!...]` negates a class
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn unwrapping_wrappers() {
+        for (wrapper, line, left) in [
+            ("sudo", "-u x vim", "vim"),
+            ("sudo", "-E --user=x -g wheel vim a.txt", "vim a.txt"),
+            ("sudo", "FOO=1 vim", "vim"),
+            ("env", "A=1 nice -n 5 htop", "nice -n 5 htop"),
+            ("env", "-u HOME -- htop", "htop"),
+            ("nice", "-n 5 htop", "htop"),
+            ("nice", "-5 htop", "htop"),
+            ("time", "-o out.txt -v make", "make"),
+            ("unknown", "-x value prog", "value prog"),
+        ] {
+            assert_eq!(unwrap(wrapper, &args(line)), args(left), "{wrapper} {line}");
+        }
+    }
+
+    #[test]
+    fn looking_through_wrappers() {
+        let entries = [
+            IgnoreEntry::Program("vim".to_string()),
+            IgnoreEntry::Program("*top".to_string()),
+        ];
+        let wrappers = args("sudo env nice");
+        let list = IgnoreList::new(&entries, wrappers).unwrap();
+        for (line, ignored) in [
+            ("sudo -u x vim", true),
+            ("env A=1 nice -n 5 htop", true),
+            ("sudo -u vim ls", false),
+            ("nice -n 5 make", false),
+            ("time vim", false),
+        ] {
+            assert_eq!(list.matching(&args(line)).is_some(), ignored, "{line}");
+        }
+    }
+
+    #[test]
+    fn subcommands() {
+        for (program, line, sub) in [
+            ("git", "-C repo log -p", Some("log")),
+            ("git", "-c core.pager=cat log", Some("log")),
+            ("git", "--no-pager --git-dir=.git diff", Some("diff")),
+            ("kubectl", "-n prod edit deploy/x", Some("edit")),
+            ("tool", "-v run", Some("run")),
+            ("git", "-C repo", None),
+            ("git", "-- log", Some("log")),
+        ] {
+            let args = args(line);
+            assert_eq!(subcommand(program, &args).map(String::as_str), sub, "{program} {line}");
+        }
+    }
+
+    #[test]
+    fn globs() {
+        for (glob, name, matches) in [
+            ("*top", "htop", true),
+            ("*top", "top", true),
+            ("*top", "topper", false),
+            ("*top", "bin/htop", false),
+            ("[!abc]top", "htop", true),
+            ("[!abc]top", "btop", false),
+            ("[hb]top", "btop", true),
+            ("?vim", "nvim", true),
+            ("?vim", "vim", false),
+            ("a.b", "a.b", true),
+            ("a.b", "axb", false),
+            ("/opt/*/run", "/opt/x/run", true),
+            ("/opt/*/run", "/opt/x/y/run", false),
+        ] {
+            let re = Regex::new(&glob_to_regex(glob)).unwrap();
+            assert_eq!(re.is_match(name), matches, "{glob} on {name}");
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// src/input.rs
+
+// --------------------------------------------------------------------------------
+// `--capture-stdin`: recording what's typed into the child (answers to an
+// installer's questions, lines fed to a REPL) as a stream of its own.
+//
+// Whatever is typed while the terminal has echo turned off is, as far as the
+// program is concerned, a password: by default it's logged as "[REDACTED]".
+// --------------------------------------------------------------------------------
+use std::{
+    io::{self, Read, Write},
+    mem,
+    os::fd::RawFd,
+    thread,
+};
+
+use crate::{
+    logfile::{SharedLog, Stream},
+    redact::{self, Redactor, Rules},
+    tee,
+};
+
+/// Logs input on its way to the child, a chunk at a time
+pub struct Recorder {
+    log: SharedLog,
+    redactor: Option<Redactor>,
+    /// The terminal whose echo setting says whether input is meant to be seen
+    tty: Option<RawFd>,
+    /// What to log in place of a line typed with echo off; `None` to log it
+    /// as it is
+    unechoed: Option<Vec<u8>>,
+    /// In the middle of a line typed with echo off
+    hiding: bool,
+    /// Writing to the log still works
+    ok: bool,
+}
+
+impl Recorder {
+    /// Record into `log`, minus the secrets `redact` finds and, with
+    /// `redact_unechoed`, anything typed while the terminal (see `watching`)
+    /// doesn't echo
+    pub fn new(log: SharedLog, redact: Option<Rules>, redact_unechoed: bool) -> Recorder {
+        let replacement = match &redact {
+            Some(rules) => rules.replacement().to_vec(),
+            None => redact::DEFAULT_REPLACEMENT.as_bytes().to_vec(),
+        };
+        Recorder {
+            log,
+            redactor: redact.map(Redactor::new),
+            tty: None,
+            unechoed: redact_unechoed.then_some(replacement),
+            hiding: false,
+            ok: true,
+        }
+    }
+
+    /// Tell input typed with echo off by the terminal `tty`
+    pub fn watching(mut self, tty: RawFd) -> Recorder {
+        self.tty = Some(tty);
+        self
+    }
+
+    /// Log one chunk of input, just read and about to go to the child
+    pub fn record(&mut self, chunk: &[u8]) {
+        if !self.ok {
+            return;
+        }
+
+        // 1. A line typed without echo becomes the replacement, once
+        let hidden;
+        let mut chunk = chunk;
+        if let Some(replacement) = &self.unechoed {
+            if self.hiding || !self.tty.is_none_or(echoes) {
+                let mut out = Vec::new();
+                for &b in chunk {
+                    if b == b'\n' || b == b'\r' {
+                        out.push(b);
+                        self.hiding = false;
+                    } else if !mem::replace(&mut self.hiding, true) {
+                        out.extend_from_slice(replacement);
+                    }
+                }
+                hidden = out;
+                chunk = &hidden;
+            }
+        }
+
+        // 2. Then the usual redaction, a line at a time
+        let redacted;
+        if let Some(redactor) = &mut self.redactor {
+            redacted = redactor.push(chunk);
+            chunk = &redacted;
+        }
+        self.ok = tee::write_log(&self.log, Stream::Stdin, chunk);
+    }
+
+    /// Log what the redactor still holds, once the input has ended
+    pub fn finish(&mut self) {
+        if let Some(redactor) = &mut self.redactor {
+            if self.ok {
+                tee::write_log(&self.log, Stream::Stdin, &redactor.finish());
+            }
+        }
+    }
+}
+
+/// Spawn a thread that copies our stdin to `sink` (the child's stdin, or its
+/// PTY), having `recorder` log it on the way. It's never joined: it sits in
+/// read() until our stdin ends, and then closes `sink`.
+///
+/// Closing a PTY isn't an end of input to the child, so there `eof` is the
+/// character that is (the terminal's VEOF, usually ^D), written when our
+/// stdin ends, the way script(1) does.
+pub fn spawn_proxy<W>(mut sink: W, mut recorder: Option<Recorder>, eof: Option<u8>)
+where
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut stdin = io::stdin().lock();
+        let mut at_line_start = true;
+        loop {
+            let n = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&buf[..n]);
+            }
+            if sink
+                .write_all(&buf[..n])
+                .and_then(|_| sink.flush())
+                .is_err()
+            {
+                break;
+            }
+            at_line_start = buf[n - 1] == b'\n';
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder.finish();
+        }
+        // VEOF only ends the input on an empty line; on a partial one, the
+        // first just hands that over
+        if let Some(eof) = eof {
+            let times = if at_line_start { 1 } else { 2 };
+            let _ = sink.write_all(&vec![eof; times]).and_then(|_| sink.flush());
+        }
+    });
+}
+
+/// Does the terminal `fd` echo what's typed? Anything but a terminal does not
+/// hide input, so counts as echoing.
+fn echoes(fd: RawFd) -> bool {
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } == -1 {
+        return true;
+    }
+    termios.c_lflag & libc::ECHO != 0
+}
@@ -0,0 +1,99 @@
+// src/list.rs
+
+// --------------------------------------------------------------------------------
+// `stash list`: browse the recorded runs, newest first.
+// --------------------------------------------------------------------------------
+use clap::Args;
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    meta::RunMeta,
+    store::{self, Run, RunFilter},
+    units,
+};
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    #[clap(flatten)]
+    filter: RunFilter,
+
+    /// Show at most this many runs
+    #[clap(short = 'n', long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Print the runs as a JSON array, with every metadata field
+    #[clap(long)]
+    json: bool,
+}
+
+/// A run as it appears in `--json` output
+#[derive(Serialize)]
+struct JsonRun<'a> {
+    id: &'a str,
+    log: &'a PathBuf,
+    size: u64,
+    #[serde(flatten)]
+    meta: Option<&'a RunMeta>,
+}
+
+pub fn run(log_dir: &Path, args: &ListArgs) -> io::Result<()> {
+    // 1. Gather matching runs, newest first
+    let runs: Vec<Run> = store::runs(log_dir)?
+        .into_iter()
+        .rev()
+        .filter(|run| args.filter.matches(run))
+        .take(args.limit.unwrap_or(usize::MAX))
+        .collect();
+
+    let mut out = io::stdout().lock();
+
+    // 2a. For scripts: everything we know, as JSON
+    if args.json {
+        let json: Vec<JsonRun> = runs
+            .iter()
+            .map(|run| JsonRun {
+                id: &run.id,
+                log: &run.log,
+                size: run.size,
+                meta: run.meta.as_ref(),
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut out, &json)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    // 2b. For humans: one line per run
+    writeln!(
+        out,
+        "{:<19}  {:>3}  {:>7}  {:>8}  {:>9}  COMMAND",
+        "ID", "PIN", "STATUS", "DURATION", "SIZE"
+    )?;
+    for run in &runs {
+        // A trailing "+" marks logs cut short by max_run_size
+        let mut size = units::format_size(run.size);
+        if run.meta.as_ref().is_some_and(|m| m.truncated) {
+            size.push('+');
+        }
+        let pin = if run.pinned() { "*" } else { "" };
+        let (status, duration, command) = match &run.meta {
+            Some(meta) => (
+                meta.status_label(),
+                meta.duration_ms
+                    .map_or("-".to_string(), units::format_duration),
+                meta.command_line(),
+            ),
+            None => ("?".to_string(), "-".to_string(), "?".to_string()),
+        };
+        writeln!(
+            out,
+            "{:<19}  {:>3}  {:>7}  {:>8}  {:>9}  {}",
+            run.id, pin, status, duration, size, command
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,599 @@
+// src/logfile.rs
+
+// --------------------------------------------------------------------------------
+// The on-disk log formats, and the writer the tee-threads share:
+//   raw   -> "X.log":   the bytes exactly as the command printed them
+//   jsonl -> "X.jsonl": one JSON object per captured chunk, e.g.
+//            {"ts":"2025-07-12T15:30:45.123456789+02:00","stream":"stderr","data":"oops\n"}
+//            (chunks that aren't valid UTF-8 carry "b64" instead of "data")
+// Either can be compressed as it's written, which adds ".gz" or ".zst" to the name,
+// and come with a plain-text copy ("X.plain.log") that has escape sequences removed.
+// --------------------------------------------------------------------------------
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::ansi::Stripper;
+
+/// How often a compressed log is flushed, so that what's on disk can be read
+/// back (and survives a crash) while the command is still running
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a run's output is stored
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Plain bytes, stdout and stderr merged as they arrived
+    #[default]
+    Raw,
+    /// One JSON record per chunk with a timestamp and the stream it came from
+    Jsonl,
+}
+
+impl LogFormat {
+    /// File extension used for logs in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Raw => "log",
+            LogFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// Work out a log's format from its file name
+    pub fn from_path(path: &Path) -> Option<LogFormat> {
+        let path = uncompressed(path);
+        let ext = path.extension().and_then(|s| s.to_str())?;
+        [LogFormat::Raw, LogFormat::Jsonl]
+            .into_iter()
+            .find(|f| f.extension() == ext)
+    }
+}
+
+/// How a log file is compressed
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Not at all
+    #[default]
+    None,
+    /// gzip, readable with zcat & co.
+    Gzip,
+    /// zstd: faster than gzip, and smaller logs
+    Zstd,
+}
+
+impl Compression {
+    /// Extension added after the format's own, if any
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    /// Work out a log's compression from its file name
+    pub fn from_path(path: &Path) -> Compression {
+        let ext = path.extension().and_then(|s| s.to_str());
+        [Compression::Gzip, Compression::Zstd]
+            .into_iter()
+            .find(|c| c.extension() == ext)
+            .unwrap_or_default()
+    }
+}
+
+/// Name of the log file of run `id`, e.g. "20250712-153045.123.jsonl.zst"
+pub fn file_name(id: &str, format: LogFormat, compression: Compression) -> String {
+    match compression.extension() {
+        Some(ext) => format!("{id}.{}.{ext}", format.extension()),
+        None => format!("{id}.{}", format.extension()),
+    }
+}
+
+/// `path` without its compression extension: "X.log.gz" -> "X.log"
+pub fn uncompressed(path: &Path) -> PathBuf {
+    match Compression::from_path(path) {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    }
+}
+
+/// The id of the run that `log` belongs to: "X.jsonl.gz" -> "X"
+pub fn run_id(log: &Path) -> Option<String> {
+    let id = uncompressed(log).file_stem()?.to_str()?.to_string();
+    Some(id)
+}
+
+/// The plain-text copy kept next to `log`: "X.log.zst" -> "X.plain.log.zst"
+pub fn plain_copy(log: &Path) -> PathBuf {
+    let name = log.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let id = run_id(log).unwrap_or_default();
+    let rest = name.strip_prefix(id.as_str()).unwrap_or_default();
+    log.with_file_name(format!("{id}.plain{rest}"))
+}
+
+/// Is `path` one of our log files (in any format)? Plain-text copies don't
+/// count; they belong to the log next to them.
+pub fn is_log(path: &Path) -> bool {
+    LogFormat::from_path(path).is_some() && !run_id(path).is_some_and(|id| id.ends_with(".plain"))
+}
+
+/// Which of the child's streams a chunk came from: its output, or (with
+/// `--capture-stdin`) what was typed into it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    Stdin,
+}
+
+/// One line of a `jsonl` log
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Record {
+    pub ts: DateTime<Local>,
+    pub stream: Stream,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b64: Option<String>,
+}
+
+/// A log writer shared between the tee-threads
+pub type SharedLog = Arc<Mutex<LogWriter>>;
+
+/// Where a `LogWriter`'s bytes go: straight into the file, or through a compressor
+enum Sink {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Sink {
+    fn new(file: File, compression: Compression) -> io::Result<Sink> {
+        Ok(match compression {
+            Compression::None => Sink::Plain(file),
+            Compression::Gzip => Sink::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Compression::Zstd => Sink::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    /// Write out the end of the compressed stream
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Gzip(gz) => gz.try_finish(),
+            Sink::Zstd(zst) => zst.do_finish(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(file) => file.write(buf),
+            Sink::Gzip(gz) => gz.write(buf),
+            Sink::Zstd(zst) => zst.write(buf),
+        }
+    }
+
+    /// For a compressor, ends the current block so everything written so far
+    /// can be decompressed
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Gzip(gz) => gz.flush(),
+            Sink::Zstd(zst) => zst.flush(),
+        }
+    }
+}
+
+/// Writes captured chunks to the logfile in the chosen format
+pub struct LogWriter {
+    file: Sink,
+    format: LogFormat,
+    /// Trailing bytes of an incomplete UTF-8 sequence, per stream, held back
+    /// so a character split across two reads doesn't force a "b64" record
+    pending: [Vec<u8>; 3],
+    /// Stop logging once this many bytes of output have been captured
+    limit: Option<u64>,
+    /// Bytes of output captured so far
+    captured: u64,
+    /// Written to since the last flush?
+    dirty: bool,
+    last_flush: Instant,
+    /// `finish` was called; nothing more gets written
+    finished: bool,
+    /// Per stream, when escape sequences are stripped before they're stored
+    strippers: Option<[Stripper; 3]>,
+    /// Another writer that gets a plain-text copy of everything
+    plain: Option<Box<LogWriter>>,
+}
+
+impl LogWriter {
+    pub fn new(file: File, format: LogFormat, compression: Compression) -> io::Result<LogWriter> {
+        Ok(LogWriter {
+            file: Sink::new(file, compression)?,
+            format,
+            pending: Default::default(),
+            limit: None,
+            captured: 0,
+            dirty: false,
+            last_flush: Instant::now(),
+            finished: false,
+            strippers: None,
+            plain: None,
+        })
+    }
+
+    /// Store plain text: remove escape sequences (colors, cursor movement, ...)
+    /// from the output before it's written
+    pub fn stripping_ansi(mut self) -> LogWriter {
+        self.strippers = Some(Default::default());
+        self
+    }
+
+    /// Also hand everything to `plain`, which should be `stripping_ansi`
+    pub fn with_plain_copy(mut self, plain: LogWriter) -> LogWriter {
+        self.plain = Some(Box::new(plain));
+        self
+    }
+
+    /// Capture at most `limit` bytes of output; anything after that is only
+    /// shown on the terminal
+    pub fn with_limit(mut self, limit: Option<u64>) -> LogWriter {
+        self.limit = limit;
+        self
+    }
+
+    /// Did we stop capturing because the output outgrew the limit?
+    pub fn truncated(&self) -> bool {
+        self.limit.is_some_and(|limit| self.captured > limit)
+    }
+
+    /// Wrap the writer up so it can be handed to several tee-threads
+    pub fn shared(self) -> SharedLog {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Append one chunk of output from `stream`
+    pub fn write_chunk(&mut self, stream: Stream, chunk: &[u8]) -> io::Result<()> {
+        // Input can still trickle in after the command is done
+        if self.finished {
+            return Ok(());
+        }
+
+        // Only keep what still fits under the limit
+        let room = self.limit.map_or(chunk.len() as u64, |limit| {
+            limit.saturating_sub(self.captured)
+        });
+        self.captured += chunk.len() as u64;
+        let chunk = &chunk[..chunk.len().min(room as usize)];
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(plain) = &mut self.plain {
+            plain.write_chunk(stream, chunk)?;
+        }
+        let stripped;
+        let chunk = match &mut self.strippers {
+            Some(strippers) => {
+                stripped = strippers[stream as usize].strip(chunk);
+                &stripped[..]
+            }
+            None => chunk,
+        };
+
+        match self.format {
+            LogFormat::Raw => self.file.write_all(chunk)?,
+            LogFormat::Jsonl => {
+                let mut bytes = std::mem::take(&mut self.pending[stream as usize]);
+                bytes.extend_from_slice(chunk);
+
+                // Hold back an incomplete multi-byte character at the very end
+                if let Err(e) = std::str::from_utf8(&bytes) {
+                    if e.error_len().is_none() {
+                        self.pending[stream as usize] = bytes.split_off(e.valid_up_to());
+                    }
+                }
+                self.write_record(stream, &bytes)?
+            }
+        }
+        self.dirty = true;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Push what's been written so far out to the file, if anything
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(plain) = &mut self.plain {
+            plain.flush()?;
+        }
+        self.last_flush = Instant::now();
+        if !self.dirty || self.finished {
+            return Ok(());
+        }
+        self.dirty = false;
+        self.file.flush()
+    }
+
+    /// Flush whatever is still held back; call once the tee-threads are done
+    pub fn finish(&mut self) -> io::Result<()> {
+        for stream in [Stream::Stdout, Stream::Stderr, Stream::Stdin] {
+            let bytes = std::mem::take(&mut self.pending[stream as usize]);
+            self.write_record(stream, &bytes)?;
+        }
+        if let Some(plain) = &mut self.plain {
+            plain.finish()?;
+        }
+        self.finished = true;
+        self.file.finish()
+    }
+
+    fn write_record(&mut self, stream: Stream, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let (data, b64) = match std::str::from_utf8(bytes) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (None, Some(BASE64.encode(bytes))),
+        };
+        let record = Record {
+            ts: Local::now(),
+            stream,
+            data,
+            b64,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Spawn a thread that flushes `log` every `FLUSH_INTERVAL` until it's
+/// finished, for when the command goes quiet with output still sitting in the
+/// compressor
+pub fn spawn_flusher(log: SharedLog) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(FLUSH_INTERVAL);
+        let mut log = log.lock().unwrap();
+        if log.finished {
+            return;
+        }
+        if log.last_flush.elapsed() >= FLUSH_INTERVAL {
+            let _ = log.flush();
+        }
+    })
+}
+
+/// A chunk of output read back from a log
+pub struct Chunk {
+    /// Which stream it came from; `None` for raw logs, which don't say
+    pub stream: Option<Stream>,
+    pub data: Vec<u8>,
+}
+
+/// Reads a log back, in whichever format it was written, decompressing as needed
+pub struct LogReader {
+    inner: BufReader<Box<dyn Read>>,
+    format: LogFormat,
+}
+
+impl LogReader {
+    pub fn open(path: &Path) -> io::Result<LogReader> {
+        let format = LogFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a stash log", path.display()),
+            )
+        })?;
+        let file = File::open(path)?;
+        let inner: Box<dyn Read> = match Compression::from_path(path) {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(Unfinished(MultiGzDecoder::new(file))),
+            Compression::Zstd => Box::new(Unfinished(zstd::Decoder::new(file)?)),
+        };
+        Ok(LogReader {
+            inner: BufReader::new(inner),
+            format,
+        })
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// The next chunk of output, or `None` at the end of the log
+    pub fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        match self.format {
+            LogFormat::Raw => {
+                let mut data = vec![0u8; 64 * 1024];
+                let n = loop {
+                    match self.inner.read(&mut data) {
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        other => break other?,
+                    }
+                };
+                if n == 0 {
+                    return Ok(None);
+                }
+                data.truncate(n);
+                Ok(Some(Chunk { stream: None, data }))
+            }
+            LogFormat::Jsonl => {
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    if self.inner.read_until(b'\n', &mut line)? == 0 {
+                        return Ok(None);
+                    }
+                    // A line that doesn't parse is most likely the half-written
+                    // last record of a run that was killed; skip it
+                    let Ok(record) = serde_json::from_slice::<Record>(&line) else {
+                        continue;
+                    };
+                    let data = match (record.data, record.b64) {
+                        (Some(text), _) => text.into_bytes(),
+                        (None, Some(b64)) => BASE64
+                            .decode(b64)
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                        (None, None) => continue,
+                    };
+                    return Ok(Some(Chunk {
+                        stream: Some(record.stream),
+                        data,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// A decompressor that takes a stream which stops short (a run that's still
+/// going, or was killed) as ending after the last complete block
+struct Unfinished<R>(R);
+
+impl<R: Read> Read for Unfinished<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A fresh path for test `test`'s log
+    fn log_path(test: &str, format: LogFormat, compression: Compression) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stash-test-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(file_name("run", format, compression))
+    }
+
+    /// Write `chunks` into a log at `path` and finish it
+    fn write_log(path: &Path, chunks: &[(Stream, &[u8])]) {
+        let format = LogFormat::from_path(path).unwrap();
+        let file = File::create(path).unwrap();
+        let mut log = LogWriter::new(file, format, Compression::from_path(path)).unwrap();
+        for (stream, chunk) in chunks {
+            log.write_chunk(*stream, chunk).unwrap();
+        }
+        log.finish().unwrap();
+    }
+
+    /// Everything `LogReader` gets back from `path`, chunk by chunk
+    fn read_log(path: &Path) -> Vec<(Option<Stream>, Vec<u8>)> {
+        let mut reader = LogReader::open(path).unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            chunks.push((chunk.stream, chunk.data));
+        }
+        chunks
+    }
+
+    #[test]
+    fn character_split_across_chunks_stays_text() {
+        let path = log_path("split-char", LogFormat::Jsonl, Compression::None);
+        // "é" is C3 A9; stderr gets a line in between the two halves
+        write_log(
+            &path,
+            &[
+                (Stream::Stdout, b"caf\xc3"),
+                (Stream::Stderr, b"oops\n"),
+                (Stream::Stdout, b"\xa9\n"),
+            ],
+        );
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("b64"), "{text}");
+        assert_eq!(
+            read_log(&path),
+            [
+                (Some(Stream::Stdout), b"caf".to_vec()),
+                (Some(Stream::Stderr), b"oops\n".to_vec()),
+                (Some(Stream::Stdout), "é\n".as_bytes().to_vec()),
+            ]
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bytes_that_arent_utf8_fall_back_to_b64() {
+        let path = log_path("b64", LogFormat::Jsonl, Compression::None);
+        // Invalid in the middle, and a character that never gets finished
+        write_log(&path, &[(Stream::Stdout, b"a\xffb\n"), (Stream::Stderr, b"c\xc3")]);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let records: Vec<Record> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].data.is_none() && records[0].b64.is_some());
+        assert_eq!(records[1].data.as_deref(), Some("c"));
+        assert!(records[2].data.is_none() && records[2].b64.is_some());
+        assert_eq!(
+            read_log(&path),
+            [
+                (Some(Stream::Stdout), b"a\xffb\n".to_vec()),
+                (Some(Stream::Stderr), b"c".to_vec()),
+                (Some(Stream::Stderr), b"\xc3".to_vec()),
+            ]
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn compressed_logs_read_back() {
+        let chunks: &[(Stream, &[u8])] = &[(Stream::Stdout, b"one\n"), (Stream::Stderr, b"two\n")];
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = log_path("compressed-raw", LogFormat::Raw, compression);
+            write_log(&path, chunks);
+            let data: Vec<u8> = read_log(&path).into_iter().flat_map(|(_, data)| data).collect();
+            assert_eq!(data, b"one\ntwo\n", "{compression:?}");
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+            let path = log_path("compressed-jsonl", LogFormat::Jsonl, compression);
+            write_log(&path, chunks);
+            let expected: Vec<_> = chunks.iter().map(|(s, d)| (Some(*s), d.to_vec())).collect();
+            assert_eq!(read_log(&path), expected, "{compression:?}");
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn unfinished_stream_ends_at_the_last_flush() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            // Like a run that's still going: flushed once, never finished
+            let path = log_path("unfinished", LogFormat::Jsonl, compression);
+            let file = File::create(&path).unwrap();
+            let mut log = LogWriter::new(file, LogFormat::Jsonl, compression).unwrap();
+            log.write_chunk(Stream::Stdout, b"first\n").unwrap();
+            log.flush().unwrap();
+            log.write_chunk(Stream::Stdout, b"second\n").unwrap();
+
+            assert_eq!(
+                read_log(&path),
+                [(Some(Stream::Stdout), b"first\n".to_vec())],
+                "{compression:?}"
+            );
+            drop(log);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+}
@@ -1,206 +1,462 @@
 // src/main.rs
 
+// --------------------------------------------------------------------------------
+// `debug!`, for every module: it has to be defined before the `mod`s below
+// --------------------------------------------------------------------------------
+/// Trace what stash decides, and why, on stderr when $STASH_DEBUG is set
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if std::env::var_os("STASH_DEBUG").is_some_and(|v| !v.is_empty()) {
+            eprintln!("stash: debug: {}", format!($($arg)*));
+        }
+    };
+}
+
+mod ansi;
+mod config;
+mod config_cmd;
+mod grep;
+mod ignore;
+mod input;
+mod list;
+mod logfile;
+mod meta;
+mod pin;
+mod pty;
+mod redact;
+mod rotate;
+mod show;
+mod signals;
+mod store;
+mod tee;
+mod timeout;
+mod trust;
+mod units;
+
 // --------------------------------------------------------------------------------
 // External crates for:
 //  1) CLI parsing (Clap)
 //  2) Timestamping (Chrono)
 //  3) Expanding “~” in paths (Dirs)
 // --------------------------------------------------------------------------------
+use ansi::AnsiMode;
 use chrono::Local;
-use clap::Parser;
-use dirs::home_dir;
-use serde::Deserialize;
+use clap::{Parser, Subcommand};
+use config::Config;
+use input::Recorder;
+use logfile::{Compression, LogFormat, LogWriter, SharedLog, Stream};
+use meta::RunMeta;
+use redact::Rules;
+use rotate::rotate_old;
+use signals::Origin;
 use std::{
-    fs,
-    io::{self, BufRead, BufReader, Read, Write},
-    path::PathBuf,
-    process::{Command, Stdio},
-    sync::{Arc, Mutex},
-    thread::{self, JoinHandle},
+    convert::Infallible,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::JoinHandle,
 };
-
-#[derive(Deserialize, Debug)]
-struct ConfigFile {
-    /// list of program names for which we skip logging entirely
-    ignore: Option<Vec<String>>,
-}
-
-fn load_config_file() -> ConfigFile {
-    // find $XDG_CONFIG_HOME/stash/stash.toml (or fallback to ~/.config)
-    let config_path = dirs::config_dir()
-        .expect("no config dir")
-        .join("stash")
-        .join("stash.toml");
-
-    if let Ok(toml_str) = std::fs::read_to_string(&config_path) {
-        toml::from_str(&toml_str)
-            .unwrap_or_else(|e| panic!("invalid TOML in {:?}: {}", config_path, e))
-    } else {
-        ConfigFile { ignore: None }
-    }
-}
+use tee::spawn_tee;
 
 /// Command‐line options, parsed via Clap
 #[derive(Parser)]
 #[clap(
     name = "stash",
     version = "0.1",
-    about = "Run any command, tee its output to a timestamped log, and keep only the last N logs."
+    about = "Run any command, tee its output to a timestamped log, and keep only the last N logs.",
+    subcommand_negates_reqs = true
 )]
 struct Opts {
-    /// Directory in which to keep per‐command logs
-    #[clap(
-        long,
-        default_value = "~/.cache/stash",
-        help = "Where to store rolling logs of past commands"
-    )]
-    log_dir: PathBuf,
-
-    /// How many logs to keep around before pruning
-    #[clap(long, default_value = "20", help = "Max number of log files to retain")]
-    retain: usize,
-
-    /// Override or add to the list of commands we ignore (e.g. tui apps)
-    /// (space separated)
-    #[clap(long, value_name = "PROG", num_args = 1..)]
-    ignore: Vec<String>,
+    /// Read this config file instead of ~/.config/stash/stash.toml (also
+    /// $STASH_CONFIG)
+    #[clap(long = "config", global = true, value_name = "FILE")]
+    config_file: Option<PathBuf>,
+
+    /// Don't run anything if a config file has errors or unknown keys, rather
+    /// than warn about them and go on without
+    #[clap(long, global = true)]
+    strict_config: bool,
+
+    /// Every setting that stash.toml can make too
+    #[clap(flatten)]
+    config: Config,
+
+    /// Run the command with plain pipes, even if stash.toml says `pty = true`
+    #[clap(long, overrides_with = "pty")]
+    no_pty: bool,
+
+    /// Work with the recorded logs instead of running a command
+    #[clap(subcommand)]
+    command: Option<StashCommand>,
 
     /// The actual command (and its args) to run; everything after `--`
     #[clap(required = true, last = true, help = "The command to run and log")]
     cmd: Vec<String>,
 }
 
+#[derive(Subcommand)]
+enum StashCommand {
+    /// List recorded runs, newest first
+    List(list::ListArgs),
+
+    /// Print the log of a recorded run
+    Show(show::ShowArgs),
+
+    /// Print the log of the most recent run (of a program)
+    Last(show::LastArgs),
+
+    /// Search all recorded logs for a regex
+    Grep(grep::GrepArgs),
+
+    /// Keep a run's log for good: pruning skips it, and it doesn't count
+    /// against any limit
+    Pin(pin::PinArgs),
+
+    /// Let a pinned run be pruned again
+    Unpin(pin::PinArgs),
+
+    /// Approve a project's `.stash.toml`, so it's used (until it changes)
+    Trust(trust::TrustArgs),
+
+    /// Stop using a project's `.stash.toml`
+    Untrust(trust::TrustArgs),
+
+    /// Inspect the configuration: `show` it, `check` the files, or `explain`
+    /// what would happen to a command
+    Config(config_cmd::ConfigArgs),
+}
+
 fn main() -> io::Result<()> {
     // 1. Parse CLI args
     let mut opts = Opts::parse();
+    if opts.no_pty {
+        opts.config.pty = Some(false);
+    }
 
-    // 2. Expand "~" to the user’s home directory, if present
-    if let Some(home) = home_dir() {
-        if opts.log_dir.starts_with("~") {
-            // Strip the "~" and join with the real home path
-            opts.log_dir = home.join(opts.log_dir.strip_prefix("~").unwrap());
+    // 2. Approving and inspecting config files is about the files themselves,
+    //    so it comes before reading them (and warning about what's in them)
+    let user_file = opts.config_file.take().or_else(|| {
+        env::var_os("STASH_CONFIG")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    });
+    let result = match &opts.command {
+        Some(StashCommand::Trust(args)) => Some(trust::set(args, true).map(|_| true)),
+        Some(StashCommand::Untrust(args)) => Some(trust::set(args, false).map(|_| true)),
+        Some(StashCommand::Config(args)) => {
+            Some(config_cmd::run(args, &opts.config, user_file.as_deref()))
+        }
+        _ => None,
+    };
+    match result {
+        Some(Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        Some(Err(e)) => {
+            eprintln!("stash: {}", e);
+            std::process::exit(1);
         }
+        // `stash config check` found problems
+        Some(Ok(false)) => std::process::exit(1),
+        Some(Ok(true)) => return Ok(()),
+        None => {}
     }
 
-    // 3. Ensure the log directory exists
-    fs::create_dir_all(&opts.log_dir)?;
-
-    // 4. Prune old logs so we never exceed `opts.retain`
-    rotate_old(&opts.log_dir, opts.retain)?;
+    // 3. Settle every setting: what's on the command line wins over the
+    //    command's profile in the config files, then the rest of them (the
+    //    project's over the user's), then the built-in defaults (applied as
+    //    settings are used)
+    let file_cfg = match config::load(user_file.as_deref(), opts.strict_config) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("stash: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let cli = std::mem::take(&mut opts.config);
+    let mut cfg = match opts.cmd.first() {
+        Some(prog) => Config::for_command(cli, file_cfg, prog),
+        None => cli.or(file_cfg),
+    };
+    let log_dir = cfg.log_dir();
 
-    // 5. Compute a fresh logfile name, e.g. "20250712-153045.123.log"
-    let logfile = opts
-        .log_dir
-        .join(format!("{}.log", Local::now().format("%Y%m%d-%H%M%S%.3f")));
-    let log = fs::File::create(&logfile)?;
+    // 4. Subcommands only look at the logs we already have
+    if let Some(command) = &opts.command {
+        let result = match command {
+            StashCommand::List(args) => list::run(&log_dir, args),
+            StashCommand::Show(args) => show::show(&log_dir, args),
+            StashCommand::Last(args) => show::last(&log_dir, args),
+            StashCommand::Pin(args) => pin::set(&log_dir, args, true),
+            StashCommand::Unpin(args) => pin::set(&log_dir, args, false),
+            StashCommand::Trust(_) | StashCommand::Untrust(_) | StashCommand::Config(_) => {
+                unreachable!()
+            }
+            // Like grep(1), exit with 1 when nothing matched
+            StashCommand::Grep(args) => match grep::run(&log_dir, args) {
+                Ok(false) => std::process::exit(1),
+                other => other.map(|_| ()),
+            },
+        };
+        match result {
+            // e.g. `stash show | head`: the reader went away, nothing to report
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                eprintln!("stash: {}", e);
+                std::process::exit(1);
+            }
+            Ok(()) => {}
+        }
+        return Ok(());
+    }
 
-    // 6. Load defaults from stash.toml
-    let file_cfg = load_config_file();
-    let mut ignore_list = file_cfg.ignore.unwrap_or_default();
+    // 5. Ensure the log directory exists
+    fs::create_dir_all(&log_dir)?;
 
-    // 7. Append any --ignore entries (CLI wins / extends)
-    ignore_list.extend(opts.ignore.clone());
+    // 6. Prune old logs so we never exceed `retain`, `max_age` or `max_total`
+    let pruned = rotate_old(&log_dir, &cfg.retention())?;
+    let report_pruned = cfg.report_pruned.unwrap_or(false);
+    if report_pruned {
+        rotate::report(&pruned);
+    }
 
-    // 8. Deduplicate so I don't accidentally run twice
-    ignore_list.sort();
-    ignore_list.dedup();
+    // 7. The ignore list: stash.toml's entries, plus any --ignore ones
+    let ignore_list = match cfg.ignore_list() {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("stash: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // 9. Grab the program name
+    // 8. Grab the program name
     let prog = &opts.cmd[0];
 
-    // 10. If it's in our ignore_list, exec it *directly*, inheriting stdio,
+    // 9. If it's in our ignore_list, exec it *directly*, inheriting stdio,
     //      so the user sees a normal interactive curses session- and we never log
-    if ignore_list.iter().any(|p| p == prog) {
-        let status = std::process::Command::new(prog)
-            .args(&opts.cmd[1..])
-            // inherit all stdio so the TUI app can take over your terminal
-            .stdin(std::process::Stdio::inherit())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .status()?;
-        std::process::exit(status.code().unwrap_or(1));
-    }
-
-    // 10. Launch the real child process, capturing both stdout and stderr pipes
-    let mut child = Command::new(&opts.cmd[0])
-        .args(&opts.cmd[1..])
-        // Tell Rust to give us handles to stdout/stderr so we can read them
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+    if let Some(entry) = ignore_list.matching(&opts.cmd) {
+        debug!("not logging this run: it matches ignore entry {}", entry);
+        run_unlogged(&opts.cmd)?;
+    }
+    debug!("logging this run: no ignore entry matches {}", prog);
 
-    // 11. Take the pipes out of the child and spawn two tee‐threads
-    let stdout_pipe = child.stdout.take().unwrap();
-    let stderr_pipe = child.stderr.take().unwrap();
+    // 10. Compile the redaction rules. If they're broken, run the command
+    //     unlogged rather than log the secrets they were meant to catch.
+    let redact = match Rules::new(&cfg.redact.take().unwrap_or_default()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("stash: not logging this run: {}", e);
+            match run_unlogged(&opts.cmd)? {}
+        }
+    };
+
+    // 11. Compute a fresh logfile name, e.g. "20250712-153045.123.log"
+    //     (or ".jsonl" when logging structured records, as capturing stdin
+    //     through a pipe does, plus ".gz" or ".zst" when compressing), and
+    //     with `--ansi both` its plain-text copy
+    let format = cfg.log_format();
+    let compression = cfg.compress.unwrap_or_default();
+    let start = Local::now();
+    let logfile = log_dir.join(logfile::file_name(
+        &start.format(store::ID_FORMAT).to_string(),
+        format,
+        compression,
+    ));
+    let mut log = LogWriter::new(fs::File::create(&logfile)?, format, compression)?
+        .with_limit(cfg.max_run_size);
+    match cfg.ansi.unwrap_or_default() {
+        AnsiMode::Keep => {}
+        AnsiMode::Strip => log = log.stripping_ansi(),
+        AnsiMode::Both => {
+            let plain_file = fs::File::create(logfile::plain_copy(&logfile))?;
+            let plain = LogWriter::new(plain_file, format, compression)?.stripping_ansi();
+            log = log.with_plain_copy(plain);
+        }
+    }
+    let log = log.shared();
+
+    // 12. Decide how to launch the real child process: either on a PTY (--pty, or `pty = true`
+    //     in stash.toml) or with its stdout and stderr captured through pipes
+    let use_pty = cfg.pty.unwrap_or(false);
+
+    // 13. With --capture-stdin, what's typed into the command is logged as well.
+    //     A raw log of a PTY already has it, as the terminal echoes it back
+    //     (except for passwords, which it's meant to leave out anyway).
+    let capture_stdin = cfg.capture_stdin.unwrap_or(false);
+    let recorder = if capture_stdin && use_pty && format == LogFormat::Raw {
+        debug!("not capturing stdin: the raw log of a PTY gets its echo");
+        None
+    } else {
+        let redact_unechoed = cfg.redact_unechoed.unwrap_or(true);
+        capture_stdin.then(|| Recorder::new(log.clone(), redact.clone(), redact_unechoed))
+    };
+
+    // 14. Record what we're about to run next to the log; it's rewritten with
+    //     the outcome once the command exits
+    let metafile = meta::sidecar(&logfile);
+    let mut run_meta = RunMeta::new(&opts.cmd, start, use_pty, format);
+    run_meta.pinned = cfg.pin.unwrap_or(false);
+    write_meta(&run_meta, &metafile);
+
+    // 15. Hold on to termination signals (and window resizes) from here on:
+    //     they're handled on dedicated threads, which only works if they're
+    //     blocked before any other thread exists. A command that may time out
+    //     gets a process group of its own, so all of it can be stopped,
+    //     unless --foreground keeps it in ours
+    let own_group = use_pty || (cfg.timeout.is_some() && !cfg.foreground.unwrap_or(false));
+    let mut held = signals::FORWARDED.to_vec();
+    held.push(libc::SIGWINCH);
+    signals::block(&held)?;
 
-    // 12. We clone the File handle so stdout and stderr threads can each write to it
-    let log_clone = log.try_clone()?;
-    let handle_out = spawn_tee(stdout_pipe, log_clone, false);
-    let handle_err = spawn_tee(stderr_pipe, log, true);
+    // 16. Launch it, spawning the tee-threads that copy its output (and one
+    //     that keeps flushing a compressed log while the command is quiet)
+    let (mut child, handles, raw_mode) = if use_pty {
+        let (child, tee, raw_mode) = pty::spawn(&opts.cmd, log.clone(), redact, recorder)?;
+        (child, vec![tee], raw_mode)
+    } else {
+        let (child, handles) = spawn_piped(&opts.cmd, log.clone(), redact, recorder, own_group)?;
+        (child, handles, None)
+    };
+    if compression != Compression::None {
+        logfile::spawn_flusher(log.clone());
+    }
 
-    // 13. Wait for the child to exit, then join both threads so they've finished writing
+    // 17. Pass Ctrl-C, SIGTERM & co. on to the child and keep going, so that
+    //     however it ends we still finish the log and its metadata
+    forward_signals(child.id(), own_group)?;
+
+    // 18. With --timeout, stop it if it runs for too long
+    let watchdog = cfg.timeout.map(|limit| {
+        let kill_after = cfg.kill_after.unwrap_or(timeout::DEFAULT_KILL_AFTER);
+        timeout::spawn_watchdog(child.id(), limit, kill_after, own_group, log.clone())
+    });
+
+    // 19. Wait for the child to exit, then join the tee-threads so they've finished writing
     let status = child.wait()?;
-    handle_out.join().unwrap();
-    handle_err.join().unwrap();
+    let timed_out = watchdog.is_some_and(|w| w.disarm());
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    if let Err(e) = log.lock().unwrap().finish() {
+        eprintln!("stash: failed to write log: {}", e);
+    }
+
+    // 20. Give the terminal back in cooked mode (exit() won't run destructors)
+    drop(raw_mode);
 
-    // 14. Propagate the child’s exit code as our own
-    std::process::exit(status.code().unwrap_or(1));
+    // 21. Complete the run's metadata with the end time and exit status,
+    //     keeping a `stash pin` (or unpin) made while it was running
+    run_meta.finish(&status);
+    run_meta.truncated = log.lock().unwrap().truncated();
+    run_meta.timed_out = timed_out;
+    if let Ok(on_disk) = RunMeta::read(&metafile) {
+        run_meta.pinned = on_disk.pinned;
+    }
+    write_meta(&run_meta, &metafile);
+
+    // 22. With --discard-success, only failures (and pinned runs) are worth keeping
+    let discard = cfg.discard_success.unwrap_or(false);
+    if status.success() && !timed_out && !run_meta.pinned && discard {
+        store::remove_files(&logfile);
+        if report_pruned {
+            eprintln!(
+                "stash: discarded the log of successful run {}",
+                start.format(store::ID_FORMAT)
+            );
+        }
+    }
+
+    // 23. Propagate the child’s exit status as our own, dying by the same
+    //     signal if that's how it went. A timeout is told apart by its own code.
+    if timed_out {
+        std::process::exit(timeout::EXIT_CODE);
+    }
+    signals::exit_like(status);
 }
 
-/// Spawn a thread that "tees" everything from `pipe` into both
-/// 1) the real terminal (stdout or stderr), and
-/// 2) our logfile (`writer`).
+/// Run `cmd` as it is, inheriting our stdio so a TUI app can take over the
+/// terminal, and exit like it did
+fn run_unlogged(cmd: &[String]) -> io::Result<Infallible> {
+    // Like system(3): don't let Ctrl-C take us down while the app handles it
+    signals::block(&signals::FORWARDED)?;
+    let mut command = Command::new(&cmd[0]);
+    command
+        .args(&cmd[1..])
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    signals::reset_mask(&mut command);
+    let mut child = command.spawn()?;
+    forward_signals(child.id(), false)?;
+    signals::exit_like(child.wait()?);
+}
+
+/// Pass termination signals sent to us on to the child with `pid`.
 ///
-/// Take `pipe` as any `impl Read + Send + 'static`.
-fn spawn_tee<P>(pipe: P, mut writer: fs::File, is_err: bool) -> JoinHandle<()>
-where
-    P: Read + Send + 'static,
-{
-    // Wrap the incoming pip in a buffered reader so we can read line-by-line
-    let mut reader = BufReader::new(pipe);
-
-    // Box-up either stdout or stderr behind the same trait object:
-    let term: Box<dyn Write + Send> = if is_err {
-        Box::new(io::stderr())
-    } else {
-        Box::new(io::stdout())
-    };
-    let term = Arc::new(Mutex::new(term));
-
-    // Spawn a thread that:
-    //      - loops on reader.read_line()
-    //      - writes each line to the real terminal AND to my logfile
-    thread::spawn(move || {
-        let mut line = String::new();
-        while reader.read_line(&mut line).unwrap_or(0) > 0 {
-            // a) Write to the terminal
-            {
-                let mut out = term.lock().unwrap();
-                write!(out, "{}", line).unwrap();
-            }
-            // b) append to the logfile
-            writer.write_all(line.as_bytes()).unwrap();
-            line.clear();
+/// A child on its own PTY (or with --timeout) leads its own process group,
+/// which gets everything.
+/// A child sharing our process group already got its own copy of whatever the
+/// terminal sent (Ctrl-C, hangup), so it only needs the ones sent to us directly.
+fn forward_signals(pid: u32, own_group: bool) -> io::Result<()> {
+    let pid = pid as libc::pid_t;
+    signals::spawn_handler(&signals::FORWARDED, move |sig, origin| {
+        if own_group || origin == Origin::Process {
+            signals::send(pid, sig, own_group);
         }
     })
 }
 
-/// Deletes oldest `.log` files so that only `retain` newest remain
-fn rotate_old(dir: &PathBuf, retain: usize) -> io::Result<()> {
-    // 1. Collect all ".log" entries
-    let mut logs: Vec<_> = fs::read_dir(dir)?
-        .filter_map(Result::ok)
-        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("log"))
-        .collect();
+/// Write the run's metadata sidecar. A failure here shouldn't stop the command
+/// from running, so we only warn about it.
+fn write_meta(run_meta: &RunMeta, path: &Path) {
+    if let Err(e) = run_meta.write(path) {
+        eprintln!("stash: failed to write {}: {}", path.display(), e);
+    }
+}
 
-    // 2. Sort by filename (we named them with timestamps, so this is chronological)
-    logs.sort_by_key(|e| e.path());
+/// Launch `cmd` with its stdout and stderr captured through pipes, and spawn
+/// one tee-thread per pipe that copies it to our terminal and into `log`
+/// (minus the secrets `redact` finds). With a `recorder`, its stdin is fed
+/// through a pipe as well, so what's typed into it can be logged. With
+/// `own_group`, it leads a process group of its own.
+fn spawn_piped(
+    cmd: &[String],
+    log: SharedLog,
+    redact: Option<Rules>,
+    recorder: Option<Recorder>,
+    own_group: bool,
+) -> io::Result<(Child, Vec<JoinHandle<()>>)> {
+    // 1. Tell Rust to give us handles to stdout/stderr so we can read them
+    let mut command = Command::new(&cmd[0]);
+    command
+        .args(&cmd[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if recorder.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    if own_group {
+        timeout::own_group(&mut command);
+    }
+    signals::reset_mask(&mut command);
+    let mut child = command.spawn()?;
 
-    // 3. While we have more than `retain`, delete the oldest (front of the vec)
-    while logs.len() > retain {
-        let old = logs.remove(0);
-        // ignore any error deleting old logs
-        let _ = fs::remove_file(old.path());
+    // 2. Pass on (and record) our stdin, watching the terminal it comes from
+    //    in case the command turns off its echo to ask for a password
+    if let Some(recorder) = recorder {
+        let is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        let recorder = if is_tty {
+            recorder.watching(libc::STDIN_FILENO)
+        } else {
+            recorder
+        };
+        input::spawn_proxy(child.stdin.take().unwrap(), Some(recorder), None);
     }
-    Ok(())
+
+    // 3. Take the pipes out of the child
+    let stdout_pipe = child.stdout.take().unwrap();
+    let stderr_pipe = child.stderr.take().unwrap();
+
+    // 4. Both threads share the log writer, each tagging chunks with its stream
+    let handle_out = spawn_tee(stdout_pipe, log.clone(), Stream::Stdout, redact.clone());
+    let handle_err = spawn_tee(stderr_pipe, log, Stream::Stderr, redact);
+
+    Ok((child, vec![handle_out, handle_err]))
 }
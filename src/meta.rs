@@ -0,0 +1,208 @@
+// src/meta.rs
+
+// --------------------------------------------------------------------------------
+// Per-run metadata: a small TOML sidecar next to each log, e.g.
+//   20250712-153045.123.log        <- the output
+//   20250712-153045.123.meta.toml  <- what was run, where, when, and how it ended
+// --------------------------------------------------------------------------------
+use crate::{
+    logfile::{self, LogFormat},
+    signals,
+};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    ffi::CStr,
+    fs, io,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+/// Everything we know about one recorded run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunMeta {
+    /// The command and its args, exactly as given after `--`
+    pub argv: Vec<String>,
+
+    /// Working directory the command was started in
+    pub cwd: PathBuf,
+
+    /// When the command was launched
+    pub start: DateTime<Local>,
+
+    /// When it exited (missing while it's still running, or if stash died first)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<DateTime<Local>>,
+
+    /// Wall-clock run time in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+
+    /// Exit code, if the command exited normally
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+
+    /// Signal number, if the command was killed by a signal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+
+    pub hostname: String,
+    pub user: String,
+
+    /// Version of stash that recorded the run
+    pub stash_version: String,
+
+    /// Whether the command ran on a pseudo-terminal
+    #[serde(default)]
+    pub pty: bool,
+
+    /// Format of the log file
+    #[serde(default)]
+    pub format: LogFormat,
+
+    /// The output outgrew `max_run_size`, so the log stops short
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+
+    /// Pinned runs are never pruned (`stash pin`, or `--pin` at launch)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+
+    /// The command ran past `--timeout`, and was stopped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+impl RunMeta {
+    /// Describe a run that is about to start
+    pub fn new(argv: &[String], start: DateTime<Local>, pty: bool, format: LogFormat) -> RunMeta {
+        RunMeta {
+            argv: argv.to_vec(),
+            cwd: env::current_dir().unwrap_or_default(),
+            start,
+            end: None,
+            duration_ms: None,
+            exit_code: None,
+            signal: None,
+            hostname: hostname(),
+            user: username(),
+            stash_version: env!("CARGO_PKG_VERSION").to_string(),
+            pty,
+            format,
+            truncated: false,
+            pinned: false,
+            timed_out: false,
+        }
+    }
+
+    /// Fill in the end time and how the command terminated
+    pub fn finish(&mut self, status: &ExitStatus) {
+        let end = Local::now();
+        self.duration_ms = Some((end - self.start).num_milliseconds().max(0) as u64);
+        self.end = Some(end);
+        self.exit_code = status.code();
+        self.signal = status.signal();
+    }
+
+    /// Write the record to `path`, replacing it atomically
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Read a record back from `path`
+    pub fn read(path: &Path) -> io::Result<RunMeta> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Name of the program that was run, without its directory ("cargo")
+    pub fn program(&self) -> &str {
+        let argv0 = self.argv.first().map_or("", String::as_str);
+        argv0.rsplit('/').next().unwrap_or(argv0)
+    }
+
+    /// The command line as you'd type it into a shell
+    pub fn command_line(&self) -> String {
+        self.argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Did the command fail? `None` if it hasn't finished (or we never saw it finish)
+    pub fn failed(&self) -> Option<bool> {
+        if self.timed_out {
+            return Some(true);
+        }
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => Some(code != 0),
+            (None, Some(_)) => Some(true),
+            (None, None) => None,
+        }
+    }
+
+    /// Short description of how the command ended: "0", "101", "SIGSEGV",
+    /// "timeout" or "-"
+    pub fn status_label(&self) -> String {
+        if self.timed_out {
+            return "timeout".to_string();
+        }
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(sig)) => signals::name(sig),
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+/// Quote `arg` for a POSIX shell, if it needs it
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The metadata sidecar that belongs to `log`: "X.log" (or "X.jsonl", "X.log.zst", ...) -> "X.meta.toml"
+pub fn sidecar(log: &Path) -> PathBuf {
+    logfile::uncompressed(log).with_extension("meta.toml")
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return String::new();
+    }
+    // gethostname() may not NUL-terminate a truncated name
+    buf[buf.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn username() -> String {
+    if let Ok(user) = env::var("USER").or_else(|_| env::var("LOGNAME")) {
+        return user;
+    }
+    // No login environment (cron, containers): ask the passwd database
+    let uid = unsafe { libc::getuid() };
+    let pw = unsafe { libc::getpwuid(uid) };
+    if pw.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr((*pw).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,37 @@
+// src/pin.rs
+
+// --------------------------------------------------------------------------------
+// `stash pin` / `stash unpin`: keep a run's log around for good (or stop doing so).
+// Pinned runs are skipped by pruning and don't count against any limit.
+// --------------------------------------------------------------------------------
+use clap::Args;
+use std::{io, path::Path};
+
+use crate::{meta, store};
+
+#[derive(Args, Debug)]
+pub struct PinArgs {
+    /// Which run: an id (or unique prefix of one), `last`, or `-N` for the N-th most recent
+    #[clap(value_name = "RUN", allow_negative_numbers = true)]
+    run: Option<String>,
+}
+
+/// Set whether the run picked by `args` is pinned
+pub fn set(log_dir: &Path, args: &PinArgs, pinned: bool) -> io::Result<()> {
+    // 1. The pin lives in the run's metadata, so it needs some
+    let runs = store::runs(log_dir)?;
+    let run = store::resolve(&runs, args.run.as_deref())?;
+    let Some(mut run_meta) = run.meta.clone() else {
+        return Err(io::Error::other(format!(
+            "run {} has no metadata to record the pin in",
+            run.id
+        )));
+    };
+
+    // 2. Rewrite it with the flag flipped
+    run_meta.pinned = pinned;
+    run_meta.write(&meta::sidecar(&run.log))?;
+    let verb = if pinned { "pinned" } else { "unpinned" };
+    println!("{verb} {}  {}", run.id, run_meta.command_line());
+    Ok(())
+}
@@ -0,0 +1,186 @@
+// src/pty.rs
+
+// --------------------------------------------------------------------------------
+// Running the child on a pseudo-terminal, so that tools which check isatty()
+// (cargo, git, ls, pytest, ...) keep their colors, progress bars and prompts.
+//
+// Layout:
+//   real terminal  <->  stash (raw mode)  <->  PTY master  <->  PTY slave  <->  child
+// --------------------------------------------------------------------------------
+use std::{
+    fs::File,
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    ptr,
+    thread::JoinHandle,
+};
+
+use crate::{
+    input::{self, Recorder},
+    logfile::{SharedLog, Stream},
+    redact::Rules,
+    signals,
+    tee::spawn_tee,
+};
+
+/// Start `cmd` on a fresh PTY and tee everything it prints into `log`
+/// (as `stdout`: the PTY merges both streams), minus the secrets `redact` finds.
+/// With a `recorder`, what's typed into it is logged too.
+///
+/// Returns the child, the tee thread to join once the child has exited, and
+/// the guard that puts our own terminal back into cooked mode when dropped.
+pub fn spawn(
+    cmd: &[String],
+    log: SharedLog,
+    redact: Option<Rules>,
+    recorder: Option<Recorder>,
+) -> io::Result<(Child, JoinHandle<()>, Option<RawMode>)> {
+    // 1. Allocate the PTY pair, sized like the terminal we're running in
+    let size = terminal_size();
+    let (master, slave) = open(size.as_ref())?;
+
+    // 2. Keep the PTY size in sync with ours whenever the window is resized.
+    //    This has to happen before any other threads exist (see signals.rs).
+    let winch_master = master.try_clone()?;
+    signals::spawn_handler(&[libc::SIGWINCH], move |_, _| {
+        if let Some(ws) = terminal_size() {
+            let _ = set_size(&winch_master, &ws);
+        }
+    })?;
+
+    // 3. Launch the child with the slave as stdin/stdout/stderr, in its own
+    //    session with the PTY as its controlling terminal
+    let mut command = Command::new(&cmd[0]);
+    command
+        .args(&cmd[1..])
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    signals::reset_mask(&mut command);
+    let child = command.spawn()?;
+    // `command` still holds our copies of the slave; drop them so the master
+    // sees EOF (EIO) as soon as the child and its descendants are gone
+    drop(command);
+
+    // 4. Put our own terminal into raw mode so keystrokes (including Ctrl-C)
+    //    go straight through to the child's line discipline
+    let raw = RawMode::enable(libc::STDIN_FILENO);
+
+    // 5. Copy our stdin into the PTY, whose echo setting tells us when a
+    //    password is being typed, and end it with its EOF character
+    let input = master.try_clone()?;
+    let recorder = recorder.map(|r| r.watching(input.as_raw_fd()));
+    let eof = eof_char(&input);
+    input::spawn_proxy(input, recorder, Some(eof));
+
+    // 6. Everything the child writes arrives merged on the master side
+    let tee = spawn_tee(master, log, Stream::Stdout, redact);
+
+    Ok((child, tee, raw))
+}
+
+/// Open a new PTY pair, returning `(master, slave)`.
+fn open(size: Option<&libc::winsize>) -> io::Result<(File, File)> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let winp = size.map_or(ptr::null(), |ws| ws as *const libc::winsize);
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            winp as *mut libc::winsize,
+        )
+    };
+    if rc == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // Don't leak the master into the child (the slave gets dup'd onto 0/1/2)
+    unsafe {
+        libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok((File::from_raw_fd(master), File::from_raw_fd(slave)))
+    }
+}
+
+/// Size of the terminal we're attached to, trying stdout, stdin then stderr.
+fn terminal_size() -> Option<libc::winsize> {
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut ws: libc::winsize = unsafe { mem::zeroed() };
+            let rc = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) };
+            (rc == 0 && ws.ws_row > 0 && ws.ws_col > 0).then_some(ws)
+        })
+}
+
+/// The character that ends the input on the PTY (VEOF, ^D unless changed)
+fn eof_char(master: &File) -> u8 {
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(master.as_raw_fd(), &mut termios) } == -1 {
+        return 0x04;
+    }
+    termios.c_cc[libc::VEOF]
+}
+
+/// Resize the PTY; the kernel then sends SIGWINCH to the child for us.
+fn set_size(master: &File, ws: &libc::winsize) -> io::Result<()> {
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, ws) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Puts a terminal into raw mode and restores the original settings on drop.
+///
+/// Remember that `std::process::exit` skips destructors: drop this explicitly
+/// before exiting.
+pub struct RawMode {
+    fd: libc::c_int,
+    orig: libc::termios,
+}
+
+impl RawMode {
+    /// Switch `fd` to raw mode; `None` if it isn't a terminal.
+    fn enable(fd: libc::c_int) -> Option<RawMode> {
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return None;
+            }
+            let mut orig: libc::termios = mem::zeroed();
+            if libc::tcgetattr(fd, &mut orig) == -1 {
+                return None;
+            }
+            let mut raw = orig;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) == -1 {
+                return None;
+            }
+            Some(RawMode { fd, orig })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.orig);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+// src/redact.rs
+
+// --------------------------------------------------------------------------------
+// Redaction: blanking out secrets (tokens, keys, passwords) in the output before
+// it's written to the log. The terminal still shows the output as it was.
+//
+// Configured in stash.toml:
+//   [redact]
+//   presets  = ["aws", "github", "bearer"]   # or ["all"]
+//   patterns = ['password=(?P<secret>\S+)']
+//
+// A pattern with a group named `secret` only has that group replaced, so the
+// log keeps the context ("password=[REDACTED]").
+// --------------------------------------------------------------------------------
+use regex::bytes::Regex;
+use serde::Deserialize;
+use std::{io, sync::Arc};
+
+use crate::ignore;
+
+/// What secrets are replaced with
+pub const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// Output without a newline is held back (to catch secrets split across two
+/// reads) until there's this much of it. Past that the line is redacted in
+/// pieces, and a secret straddling two pieces may slip through.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Patterns for common kinds of secrets, by preset name
+const PRESETS: &[(&str, &[&str])] = &[
+    (
+        "aws",
+        &[
+            r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+            r#"(?i)aws_secret_access_key["']?\s*[=:]\s*["']?(?P<secret>[A-Za-z0-9/+=]{40})"#,
+        ],
+    ),
+    (
+        "github",
+        &[
+            r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+            r"\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+        ],
+    ),
+    ("gitlab", &[r"\bglpat-[A-Za-z0-9_\-]{20,}\b"]),
+    ("slack", &[r"\bxox[abposr]-[A-Za-z0-9\-]{10,}\b"]),
+    (
+        "bearer",
+        &[r"(?i)\b(?:bearer|token)\s+(?P<secret>[A-Za-z0-9\-._~+/]{8,}=*)"],
+    ),
+    (
+        "jwt",
+        &[r"\beyJ[A-Za-z0-9_\-]+\.eyJ[A-Za-z0-9_\-]+\.[A-Za-z0-9_\-]+"],
+    ),
+    (
+        "url-password",
+        &[r"[a-z][a-z0-9+.\-]*://[^/\s:@]+:(?P<secret>[^/\s@]+)@"],
+    ),
+];
+
+/// The `[redact]` table of stash.toml
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct RedactConfig {
+    /// Built-in pattern sets to use, by name (see `PRESETS`), or "all"
+    #[serde(default)]
+    pub presets: Vec<String>,
+
+    /// Extra regexes (Rust `regex` syntax) for secrets of our own
+    #[serde(default)]
+    pub patterns: Vec<String>,
+
+    /// What to put in place of a secret (default "[REDACTED]")
+    pub replacement: Option<String>,
+}
+
+/// The compiled redaction rules, cheap to clone for each tee-thread
+#[derive(Clone)]
+pub struct Rules {
+    patterns: Arc<Vec<Regex>>,
+    replacement: Arc<Vec<u8>>,
+}
+
+impl Rules {
+    /// Compile the rules `config` asks for; `None` if it doesn't ask for any
+    pub fn new(config: &RedactConfig) -> io::Result<Option<Rules>> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+        // 1. Gather the patterns of the presets, then our own
+        let mut sources: Vec<&str> = Vec::new();
+        for name in &config.presets {
+            if name == "all" {
+                sources.extend(PRESETS.iter().flat_map(|(_, p)| p.iter()));
+                continue;
+            }
+            let Some((_, patterns)) = PRESETS.iter().find(|(n, _)| n == name) else {
+                let known: Vec<&str> = PRESETS.iter().map(|(n, _)| *n).collect();
+                return Err(invalid(format!(
+                    "unknown redact preset {name:?} (expected one of: all, {})",
+                    known.join(", ")
+                )));
+            };
+            sources.extend(patterns.iter());
+        }
+        sources.extend(config.patterns.iter().map(String::as_str));
+        if sources.is_empty() {
+            return Ok(None);
+        }
+
+        // 2. Compile them all
+        let mut patterns = Vec::new();
+        for source in sources {
+            let re = Regex::new(source)
+                .map_err(|e| invalid(format!("invalid redact pattern {source:?}: {}", ignore::regex_error(&e))))?;
+            patterns.push(re);
+        }
+        let replacement = config.replacement.as_deref().unwrap_or(DEFAULT_REPLACEMENT);
+        Ok(Some(Rules {
+            patterns: Arc::new(patterns),
+            replacement: Arc::new(replacement.as_bytes().to_vec()),
+        }))
+    }
+
+    /// What secrets are replaced with
+    pub fn replacement(&self) -> &[u8] {
+        &self.replacement
+    }
+
+    /// `text` with every secret in it replaced
+    fn apply(&self, text: &[u8]) -> Vec<u8> {
+        let mut text = text.to_vec();
+        for re in self.patterns.iter() {
+            if !re.is_match(&text) {
+                continue;
+            }
+            let mut out = Vec::with_capacity(text.len());
+            let mut last = 0;
+            for caps in re.captures_iter(&text) {
+                // Only the `secret` group if there is one, otherwise the whole match
+                let secret = caps.name("secret").unwrap_or_else(|| caps.get(0).unwrap());
+                out.extend_from_slice(&text[last..secret.start()]);
+                out.extend_from_slice(&self.replacement);
+                last = secret.end();
+            }
+            out.extend_from_slice(&text[last..]);
+            text = out;
+        }
+        text
+    }
+}
+
+/// Redacts one stream, a chunk at a time. Works on whole lines (ending in
+/// "\n", or "\r" as typed on a terminal), so a secret split across two chunks
+/// is still caught.
+pub struct Redactor {
+    rules: Rules,
+    /// The start of a line we haven't seen the end of yet
+    held: Vec<u8>,
+}
+
+impl Redactor {
+    pub fn new(rules: Rules) -> Redactor {
+        Redactor {
+            rules,
+            held: Vec::new(),
+        }
+    }
+
+    /// Take in the next chunk of output; returns the redacted output that's
+    /// ready to be logged, which may be less (or more) than `chunk`
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.held.extend_from_slice(chunk);
+
+        // Everything up to the last line end is ready, and so is most of a
+        // line that has grown too long to wait for
+        let ready = match self.held.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+            Some(i) => i + 1,
+            None if self.held.len() > MAX_LINE => self.held.len() - MAX_LINE / 2,
+            None => return Vec::new(),
+        };
+        let rest = self.held.split_off(ready);
+        let ready = std::mem::replace(&mut self.held, rest);
+        self.rules.apply(&ready)
+    }
+
+    /// Whatever is still held back, once the stream has ended
+    pub fn finish(&mut self) -> Vec<u8> {
+        let rest = std::mem::take(&mut self.held);
+        self.rules.apply(&rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(patterns: &[&str]) -> Redactor {
+        let config = RedactConfig {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        };
+        Redactor::new(Rules::new(&config).unwrap().unwrap())
+    }
+
+    /// Push `chunks` one after the other, then finish
+    fn redact(redactor: &mut Redactor, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut out: Vec<u8> = chunks.iter().flat_map(|chunk| redactor.push(chunk)).collect();
+        out.extend(redactor.finish());
+        out
+    }
+
+    #[test]
+    fn secret_split_across_chunks() {
+        let mut r = redactor(&[r"password=(?P<secret>\S+)"]);
+        assert_eq!(r.push(b"login ok\nuser password=hun"), b"login ok\n");
+        assert_eq!(r.push(b"ter2 done\r"), b"user password=[REDACTED] done\r");
+        assert_eq!(r.finish(), b"");
+    }
+
+    #[test]
+    fn secret_split_byte_by_byte() {
+        let mut r = redactor(&[r"\bghp_[A-Za-z0-9]{36}\b"]);
+        let line = format!("token: ghp_{}\n", "a".repeat(36));
+        let chunks: Vec<&[u8]> = line.as_bytes().chunks(1).collect();
+        assert_eq!(redact(&mut r, &chunks), b"token: [REDACTED]\n");
+    }
+
+    #[test]
+    fn last_line_without_newline_is_redacted_on_finish() {
+        let mut r = redactor(&["s3cr3t"]);
+        assert_eq!(r.push(b"the s3c"), b"");
+        assert_eq!(r.push(b"r3t"), b"");
+        assert_eq!(r.finish(), b"the [REDACTED]");
+    }
+
+    #[test]
+    fn long_line_is_let_through_in_pieces() {
+        let mut r = redactor(&["s3cr3t"]);
+        let long = vec![b'x'; MAX_LINE + 10];
+        let out = r.push(&long);
+        assert_eq!(out.len(), MAX_LINE + 10 - MAX_LINE / 2);
+
+        // What's held back still catches a secret that follows
+        let mut rest = r.push(b"s3cr3t\n");
+        assert_eq!(rest.len(), MAX_LINE / 2 + "[REDACTED]\n".len());
+        assert!(rest.ends_with(b"x[REDACTED]\n"));
+        rest.extend(r.finish());
+        assert_eq!(out.len() + rest.len(), MAX_LINE + 10 + "[REDACTED]\n".len());
+    }
+}
@@ -0,0 +1,412 @@
+// src/rotate.rs
+
+// --------------------------------------------------------------------------------
+// Pruning the log directory: which recorded runs to delete to stay within the
+// retention limits.
+// --------------------------------------------------------------------------------
+use chrono::{Duration, Local};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{
+    meta::RunMeta,
+    store::{self, Run},
+    units,
+};
+
+/// How runs are pooled when counting them against `retain`
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BucketBy {
+    /// Each program (or configured group of programs) keeps its own `retain` runs
+    #[default]
+    Program,
+    /// All runs share one pool, whatever was run
+    Global,
+}
+
+/// How much history to keep
+pub struct Retention {
+    /// Keep at most this many runs per bucket
+    pub retain: usize,
+    /// How runs are split into buckets
+    pub bucket_by: BucketBy,
+    /// Per-bucket overrides of the limits below, by program or group name
+    /// (from `retain_for`, and the programs' profiles)
+    pub limits_for: HashMap<String, Limits>,
+    /// Named groups of programs that share a bucket, e.g. build = [cargo, make]
+    pub groups: HashMap<String, Vec<String>>,
+    /// Delete runs that started longer ago than this
+    pub max_age: Option<Duration>,
+    /// Count failed runs separately from successful ones, keeping this many
+    /// per bucket (instead of sharing `retain` with them)
+    pub retain_failed: Option<usize>,
+    /// Maximum age of failed runs, instead of `max_age`
+    pub max_age_failed: Option<Duration>,
+    /// Keep the total size of all logs under this many bytes
+    pub max_total: Option<u64>,
+}
+
+/// The limits one bucket has of its own, instead of the global ones
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub retain: Option<usize>,
+    pub max_age: Option<Duration>,
+    pub retain_failed: Option<usize>,
+    pub max_age_failed: Option<Duration>,
+}
+
+impl Retention {
+    /// The bucket `run` counts against: its group, its program, or (with
+    /// `bucket_by = "global"`, or for runs we know nothing about) the shared one
+    fn bucket<'a>(&'a self, run: &'a Run) -> &'a str {
+        let Some(meta) = &run.meta else {
+            return "";
+        };
+        self.bucket_of(meta.program())
+    }
+
+    /// The bucket runs of `program` count against
+    pub fn bucket_of<'a>(&'a self, program: &'a str) -> &'a str {
+        if self.bucket_by == BucketBy::Global {
+            return "";
+        }
+        self.groups
+            .iter()
+            .find(|(_, members)| members.iter().any(|m| m == program))
+            .map_or(program, |(group, _)| group.as_str())
+    }
+
+    /// The bucket's own limits, if it has any
+    fn limits(&self, bucket: &str) -> Limits {
+        self.limits_for.get(bucket).cloned().unwrap_or_default()
+    }
+
+    /// How many failed runs `bucket` may keep, when they're counted in a
+    /// pool of their own
+    pub fn retain_failed(&self, bucket: &str) -> Option<usize> {
+        self.limits(bucket).retain_failed.or(self.retain_failed)
+    }
+
+    /// How many runs `bucket` may keep; with `retain_failed`, failed runs
+    /// are counted in a pool of their own
+    pub fn cap(&self, bucket: &str, failed: bool) -> usize {
+        match self.retain_failed(bucket) {
+            Some(retain_failed) if failed => retain_failed,
+            _ => self.limits(bucket).retain.unwrap_or(self.retain),
+        }
+    }
+
+    /// How old runs in `bucket` may get, and the setting that says so
+    /// ("max_age", or "max_age_failed for cargo"). The bucket's own ages come
+    /// first, so a profile's `max_age` also holds for its failed runs unless
+    /// it sets `max_age_failed` too.
+    fn age_limit(&self, bucket: &str, failed: bool) -> Option<(Duration, String)> {
+        let own = self.limits(bucket);
+        let ages = if failed {
+            vec![
+                (own.max_age_failed, "max_age_failed", true),
+                (own.max_age, "max_age", true),
+                (self.max_age_failed, "max_age_failed", false),
+                (self.max_age, "max_age", false),
+            ]
+        } else {
+            vec![(own.max_age, "max_age", true), (self.max_age, "max_age", false)]
+        };
+        ages.into_iter().find_map(|(age, key, own)| {
+            let key = if own {
+                format!("{key} for {bucket}")
+            } else {
+                key.to_string()
+            };
+            Some((age?, key))
+        })
+    }
+
+    /// How old runs in `bucket` may get
+    pub fn max_age(&self, bucket: &str, failed: bool) -> Option<Duration> {
+        self.age_limit(bucket, failed).map(|(age, _)| age)
+    }
+}
+
+/// Failed runs are the ones worth keeping longer. A run that never recorded
+/// how it ended (stash itself was killed, or it's still going) counts as one.
+fn failed(run: &Run) -> bool {
+    run.meta.as_ref().and_then(|m| m.failed()) != Some(false)
+}
+
+/// A run that `rotate_old` deleted, and why
+pub struct Pruned {
+    pub run: Run,
+    pub reason: String,
+}
+
+/// Deletes old runs (log and metadata) so that what remains fits `policy`:
+/// nothing older than `max_age`, only the `retain` newest of the rest in each
+/// bucket, and no more than `max_total` bytes in all. Failed runs get their
+/// own `max_age_failed` / `retain_failed` limits if set, buckets may have
+/// limits of their own (`limits_for`), and pinned runs are left alone. Returns
+/// what was deleted.
+pub fn rotate_old(dir: &Path, policy: &Retention) -> io::Result<Vec<Pruned>> {
+    // 1. Collect all runs, oldest first. Pinned ones are off limits, and
+    //    don't count against any of the limits either.
+    let mut runs: Vec<Run> = store::runs(dir)?
+        .into_iter()
+        .filter(|run| !run.pinned())
+        .collect();
+    let mut pruned = Vec::new();
+
+    // 2. Drop everything past its maximum age. The age comes from the run's
+    //    recorded start time, not the file's mtime, which a copy or touch can change.
+    let now = Local::now();
+    let age_limit = |run: &Run| policy.age_limit(policy.bucket(run), failed(run));
+    let (expired, kept): (Vec<Run>, Vec<Run>) =
+        runs.into_iter()
+            .partition(|run| match (age_limit(run), run.start()) {
+                (Some((max_age, _)), Some(start)) => start < now - max_age,
+                _ => false,
+            });
+    runs = kept;
+    pruned.extend(expired.into_iter().map(|run| {
+        let key = age_limit(&run).map_or("max_age".to_string(), |(_, key)| key);
+        Pruned {
+            run,
+            reason: format!("older than {key}"),
+        }
+    }));
+
+    // 3. In each bucket, keep the newest `retain` runs (or the bucket's own
+    //    count) and delete the rest. Walk newest first to count them off.
+    let mut seen: HashMap<(&str, bool), usize> = HashMap::new();
+    let mut over = vec![false; runs.len()];
+    for (i, run) in runs.iter().enumerate().rev() {
+        let bucket = policy.bucket(run);
+        let failed = failed(run);
+        let pool = failed && policy.retain_failed(bucket).is_some();
+        let count = seen.entry((bucket, pool)).or_default();
+        *count += 1;
+        over[i] = *count > policy.cap(bucket, failed);
+    }
+    let mut over = over.into_iter();
+    let (excess, kept): (Vec<Run>, Vec<Run>) = runs.into_iter().partition(|_| over.next().unwrap());
+    runs = kept;
+    pruned.extend(excess.into_iter().map(|run| {
+        let count = if failed(&run) && policy.retain_failed(policy.bucket(&run)).is_some() {
+            "retain_failed"
+        } else {
+            "retain"
+        };
+        let reason = match policy.bucket(&run) {
+            "" => format!("over the {count} count"),
+            bucket => format!("over the {count} count for {bucket}"),
+        };
+        Pruned { run, reason }
+    }));
+
+    // 4. Then keep deleting the oldest until the rest fits in `max_total`
+    if let Some(max_total) = policy.max_total {
+        let mut total: u64 = runs.iter().map(|run| run.size).sum();
+        let mut excess = 0;
+        while total > max_total && excess < runs.len() {
+            total -= runs[excess].size;
+            excess += 1;
+        }
+        pruned.extend(runs.drain(..excess).map(|run| Pruned {
+            run,
+            reason: "over the max_total quota".to_string(),
+        }));
+    }
+
+    for p in &pruned {
+        p.run.remove();
+    }
+    Ok(pruned)
+}
+
+/// Tell the user (on stderr) which runs were pruned, and why
+pub fn report(pruned: &[Pruned]) {
+    for p in pruned {
+        let command = p
+            .run
+            .meta
+            .as_ref()
+            .map_or("?".to_string(), RunMeta::command_line);
+        eprintln!(
+            "stash: pruned {} ({}, {}): {}",
+            p.run.id,
+            units::format_size(p.run.size),
+            command,
+            p.reason
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logfile::{self, Compression, LogFormat},
+        meta,
+    };
+    use std::{fs, path::PathBuf};
+
+    /// A log directory of its own for each test, emptied first
+    fn log_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stash-test-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Record a run of `program` that started `days_ago`, as `run_meta` and
+    /// the log file would; runs are told apart (and ordered) by `n`
+    fn add_run(dir: &Path, n: u32, program: &str, days_ago: i64, exit_code: i32, pinned: bool) {
+        let start = Local::now() - Duration::days(days_ago) + Duration::milliseconds(n.into());
+        let id = start.format(store::ID_FORMAT).to_string();
+        let log = dir.join(logfile::file_name(&id, LogFormat::Raw, Compression::None));
+        fs::write(&log, vec![b'x'; 100]).unwrap();
+        let argv = vec![program.to_string()];
+        let mut run_meta = RunMeta::new(&argv, start, false, LogFormat::Raw);
+        run_meta.exit_code = Some(exit_code);
+        run_meta.pinned = pinned;
+        run_meta.write(&meta::sidecar(&log)).unwrap();
+    }
+
+    fn policy(retain: usize) -> Retention {
+        Retention {
+            retain,
+            bucket_by: BucketBy::Program,
+            limits_for: HashMap::new(),
+            groups: HashMap::new(),
+            max_age: None,
+            retain_failed: None,
+            max_age_failed: None,
+            max_total: None,
+        }
+    }
+
+    /// What's left in `dir`: the program and exit code of each run, oldest first
+    fn left(dir: &Path) -> Vec<(String, i32)> {
+        let runs = store::runs(dir).unwrap();
+        let left = runs.iter().map(|run| {
+            let meta = run.meta.as_ref().unwrap();
+            (meta.program().to_string(), meta.exit_code.unwrap())
+        });
+        let left = left.collect();
+        fs::remove_dir_all(dir).unwrap();
+        left
+    }
+
+    fn runs(list: &[(&str, i32)]) -> Vec<(String, i32)> {
+        list.iter().map(|(p, code)| (p.to_string(), *code)).collect()
+    }
+
+    #[test]
+    fn each_program_keeps_its_own_count() {
+        let dir = log_dir("buckets");
+        for (n, program) in ["ls", "echo", "ls", "echo", "ls", "echo"].iter().enumerate() {
+            add_run(&dir, n as u32, program, 0, 0, false);
+        }
+        let pruned = rotate_old(&dir, &policy(2)).unwrap();
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(pruned[0].reason, "over the retain count for ls");
+        assert_eq!(
+            left(&dir),
+            runs(&[("ls", 0), ("echo", 0), ("ls", 0), ("echo", 0)])
+        );
+    }
+
+    #[test]
+    fn groups_and_global_buckets() {
+        let dir = log_dir("groups");
+        for (n, program) in ["cargo", "make", "ls", "cargo"].iter().enumerate() {
+            add_run(&dir, n as u32, program, 0, 0, false);
+        }
+        let mut grouped = policy(2);
+        grouped
+            .groups
+            .insert("build".to_string(), vec!["cargo".to_string(), "make".to_string()]);
+        rotate_old(&dir, &grouped).unwrap();
+        assert_eq!(left(&dir), runs(&[("make", 0), ("ls", 0), ("cargo", 0)]));
+
+        let dir = log_dir("global");
+        for (n, program) in ["cargo", "make", "ls", "cargo"].iter().enumerate() {
+            add_run(&dir, n as u32, program, 0, 0, false);
+        }
+        let mut global = policy(2);
+        global.bucket_by = BucketBy::Global;
+        let pruned = rotate_old(&dir, &global).unwrap();
+        assert_eq!(pruned[0].reason, "over the retain count");
+        assert_eq!(left(&dir), runs(&[("ls", 0), ("cargo", 0)]));
+    }
+
+    #[test]
+    fn failed_runs_have_a_pool_of_their_own() {
+        let dir = log_dir("failed");
+        for (n, code) in [1, 0, 2, 3, 0].into_iter().enumerate() {
+            add_run(&dir, n as u32, "make", 0, code, false);
+        }
+        let mut policy = policy(1);
+        policy.retain_failed = Some(2);
+        let pruned = rotate_old(&dir, &policy).unwrap();
+        let reasons: Vec<&str> = pruned.iter().map(|p| p.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            ["over the retain_failed count for make", "over the retain count for make"]
+        );
+        assert_eq!(left(&dir), runs(&[("make", 2), ("make", 3), ("make", 0)]));
+    }
+
+    #[test]
+    fn pinned_runs_are_kept_and_not_counted() {
+        let dir = log_dir("pinned");
+        add_run(&dir, 0, "ls", 30, 0, true);
+        add_run(&dir, 1, "ls", 0, 1, false);
+        add_run(&dir, 2, "ls", 0, 0, false);
+        let mut policy = policy(1);
+        policy.max_age = Some(Duration::days(7));
+        policy.max_total = Some(150);
+        rotate_old(&dir, &policy).unwrap();
+        assert_eq!(left(&dir), runs(&[("ls", 0), ("ls", 0)]));
+    }
+
+    #[test]
+    fn ages_per_bucket() {
+        let dir = log_dir("ages");
+        add_run(&dir, 0, "cargo", 20, 0, false);
+        add_run(&dir, 1, "cargo", 20, 1, false);
+        add_run(&dir, 2, "echo", 20, 0, false);
+        add_run(&dir, 3, "echo", 10, 1, false);
+        let mut policy = policy(10);
+        policy.max_age = Some(Duration::days(7));
+        policy.max_age_failed = Some(Duration::days(14));
+        policy.limits_for.insert(
+            "cargo".to_string(),
+            Limits {
+                max_age: Some(Duration::days(30)),
+                ..Default::default()
+            },
+        );
+        let pruned = rotate_old(&dir, &policy).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].reason, "older than max_age");
+        assert_eq!(
+            left(&dir),
+            runs(&[("cargo", 0), ("cargo", 1), ("echo", 1)])
+        );
+    }
+
+    #[test]
+    fn max_total_takes_the_oldest() {
+        let dir = log_dir("total");
+        for n in 0..4 {
+            add_run(&dir, n, "ls", 0, n as i32, false);
+        }
+        let mut policy = policy(10);
+        policy.max_total = Some(250);
+        let pruned = rotate_old(&dir, &policy).unwrap();
+        assert!(pruned.iter().all(|p| p.reason == "over the max_total quota"));
+        assert_eq!(left(&dir), runs(&[("ls", 2), ("ls", 3)]));
+    }
+}
@@ -0,0 +1,261 @@
+// src/show.rs
+
+// --------------------------------------------------------------------------------
+// `stash show` / `stash last`: print a recorded log.
+// --------------------------------------------------------------------------------
+use clap::Args;
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    ansi::Stripper,
+    logfile::{self, LogFormat, LogReader, Stream},
+    store::{self, Run, RunFilter},
+};
+
+#[derive(Args, Debug)]
+pub struct ShowArgs {
+    /// Which run: an id (or unique prefix of one), `last`, or `-N` for the N-th most recent
+    #[clap(value_name = "RUN", allow_negative_numbers = true)]
+    run: Option<String>,
+
+    #[clap(flatten)]
+    filter: RunFilter,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct LastArgs {
+    /// Show the last run of this program, rather than the last run overall
+    #[clap(value_name = "PROG")]
+    program: Option<String>,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+/// What to print out of a log, and where
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Only what the command wrote to stdout (needs a `jsonl` log)
+    #[clap(long, conflicts_with_all = ["stderr", "stdin"])]
+    stdout: bool,
+
+    /// Only what the command wrote to stderr (needs a `jsonl` log)
+    #[clap(long, conflicts_with = "stdin")]
+    stderr: bool,
+
+    /// Only what was typed into the command (needs a `jsonl` log of a run
+    /// with `--capture-stdin`)
+    #[clap(long)]
+    stdin: bool,
+
+    /// Only the last N lines
+    #[clap(long, value_name = "N")]
+    tail: Option<usize>,
+
+    /// Without colors or other escape sequences (from the plain-text copy
+    /// stored with `--ansi both`, if there is one)
+    #[clap(long)]
+    plain: bool,
+
+    /// Page the output through $PAGER (default `less -FRX`)
+    #[clap(short, long)]
+    pager: bool,
+}
+
+impl OutputArgs {
+    fn stream(&self) -> Option<Stream> {
+        if self.stdout {
+            Some(Stream::Stdout)
+        } else if self.stderr {
+            Some(Stream::Stderr)
+        } else if self.stdin {
+            Some(Stream::Stdin)
+        } else {
+            None
+        }
+    }
+}
+
+/// `stash show [RUN]`
+pub fn show(log_dir: &Path, args: &ShowArgs) -> io::Result<()> {
+    let runs: Vec<Run> = store::runs(log_dir)?
+        .into_iter()
+        .filter(|run| args.filter.matches(run))
+        .collect();
+    let run = store::resolve(&runs, args.run.as_deref())?;
+    print_run(run, &args.output)
+}
+
+/// `stash last [PROG]`
+pub fn last(log_dir: &Path, args: &LastArgs) -> io::Result<()> {
+    let filter = RunFilter {
+        program: args.program.clone(),
+        ..RunFilter::default()
+    };
+    let runs: Vec<Run> = store::runs(log_dir)?
+        .into_iter()
+        .filter(|run| filter.matches(run))
+        .collect();
+    let run = store::resolve(&runs, None)?;
+    print_run(run, &args.output)
+}
+
+/// Write the (selected parts of the) log of `run` to stdout or the pager
+fn print_run(run: &Run, opts: &OutputArgs) -> io::Result<()> {
+    // 1. For --plain, read the plain-text copy if we kept one, and otherwise
+    //    strip the log as we go
+    let plain_copy = logfile::plain_copy(&run.log);
+    let has_copy = opts.plain && plain_copy.exists();
+    let mut strippers = (opts.plain && !has_copy).then(<[Stripper; 3]>::default);
+    let mut reader = LogReader::open(if has_copy { &plain_copy } else { &run.log })?;
+
+    // 2. Streams can only be told apart in structured logs, and stdout and
+    //    stderr only for piped runs
+    let wanted = opts.stream();
+    if wanted.is_some() {
+        if reader.format() == LogFormat::Raw {
+            return Err(io::Error::other(format!(
+                "run {} has a raw log, which doesn't keep streams apart (record with --format jsonl)",
+                run.id
+            )));
+        }
+        if wanted != Some(Stream::Stdin) && run.meta.as_ref().is_some_and(|m| m.pty) {
+            return Err(io::Error::other(format!(
+                "run {} ran on a PTY, so stdout and stderr were logged as one stream",
+                run.id
+            )));
+        }
+    }
+
+    // 3. Copy the chunks we want, holding back only the last N lines for --tail
+    with_output(opts.pager, |out| {
+        let mut tail = opts.tail.map(Tail::new);
+        while let Some(mut chunk) = reader.next_chunk()? {
+            if wanted.is_some() && chunk.stream != wanted {
+                continue;
+            }
+            if let Some(strippers) = &mut strippers {
+                let stream = chunk.stream.unwrap_or(Stream::Stdout);
+                chunk.data = strippers[stream as usize].strip(&chunk.data);
+            }
+            match &mut tail {
+                Some(tail) => tail.push(&chunk.data),
+                None => out.write_all(&chunk.data)?,
+            }
+        }
+        if let Some(tail) = tail {
+            for line in tail.lines {
+                out.write_all(&line)?;
+            }
+        }
+        out.flush()
+    })?;
+
+    if run.meta.as_ref().is_some_and(|m| m.truncated) {
+        eprintln!("stash: the log of {} was cut short by max_run_size", run.id);
+    }
+    Ok(())
+}
+
+/// Run `f` against our stdout, or the stdin of a pager when asked for one
+/// (and stdout is a terminal).
+fn with_output<F>(pager: bool, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let is_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    if !pager || !is_tty {
+        return f(&mut io::stdout().lock());
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less -FRX".to_string());
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let result = f(&mut stdin);
+    drop(stdin);
+    child.wait()?;
+
+    // Quitting the pager early closes the pipe on us; that's not an error
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => other,
+    }
+}
+
+/// Keeps the last `keep` lines of everything pushed into it
+struct Tail {
+    keep: usize,
+    lines: VecDeque<Vec<u8>>,
+}
+
+impl Tail {
+    fn new(keep: usize) -> Tail {
+        Tail {
+            keep,
+            lines: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Continue the last line if it didn't end in a newline yet
+            let open = self.lines.back().is_some_and(|l| !l.ends_with(b"\n"));
+            if !open {
+                self.lines.push_back(Vec::new());
+            }
+            let end = data
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(data.len(), |i| i + 1);
+            self.lines
+                .back_mut()
+                .unwrap()
+                .extend_from_slice(&data[..end]);
+            data = &data[end..];
+
+            while self.lines.len() > self.keep {
+                self.lines.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a `Tail` of `keep` lines holds after being pushed `chunks`
+    fn tail(keep: usize, chunks: &[&str]) -> Vec<String> {
+        let mut tail = Tail::new(keep);
+        for chunk in chunks {
+            tail.push(chunk.as_bytes());
+        }
+        let lines = tail.lines.into_iter();
+        lines.map(|l| String::from_utf8(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn lines_split_across_chunks() {
+        assert_eq!(tail(2, &["one\ntw", "o\nthr", "ee"]), ["two\n", "three"]);
+        assert_eq!(tail(2, &["one\n", "two\n", "three\n"]), ["two\n", "three\n"]);
+        assert_eq!(tail(1, &["a\nb", "c", "\n"]), ["bc\n"]);
+        assert_eq!(tail(3, &["only\n"]), ["only\n"]);
+        assert!(tail(0, &["a\n", "b"]).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+// src/signals.rs
+
+// --------------------------------------------------------------------------------
+// Synchronous signal handling: instead of installing async signal handlers we
+// block the signals we care about and pick them up on a dedicated thread with
+// sigwait(), where it's safe to take locks, do I/O and call ioctl().
+// --------------------------------------------------------------------------------
+use std::{
+    io, mem,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command, ExitStatus},
+    ptr, thread,
+};
+
+use libc::c_int;
+
+/// Signals that ask us to stop, which we pass on to the child instead of dying
+/// on the spot, so that its log and metadata still get written
+pub const FORWARDED: [c_int; 4] = [libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGTERM];
+
+/// Who sent a signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The kernel, e.g. the terminal driver on Ctrl-C or hangup. These go to
+    /// the whole foreground process group, so a child sharing ours has
+    /// received its own copy.
+    Kernel,
+    /// Another process, via kill(2) and friends
+    Process,
+}
+
+/// Block `sigs` for the calling thread and every thread it spawns from now on,
+/// so they stay pending until a `spawn_handler` thread picks them up.
+pub fn block(sigs: &[c_int]) -> io::Result<()> {
+    let set = sigset(sigs);
+    let rc = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    Ok(())
+}
+
+/// Block `sigs` for the calling thread, then spawn a thread that waits for them
+/// and calls `handler` with each one as it arrives.
+///
+/// Must be called before any other threads are spawned (or after `block`ing
+/// `sigs` early on): the signal mask is inherited by new threads, and a signal
+/// that lands on a thread which doesn't block it gets its default disposition
+/// instead of reaching `handler`. Children inherit the mask as well, so spawn
+/// them through `reset_mask`.
+pub fn spawn_handler<F>(sigs: &[c_int], mut handler: F) -> io::Result<()>
+where
+    F: FnMut(c_int, Origin) + Send + 'static,
+{
+    block(sigs)?;
+    let set = sigset(sigs);
+    thread::spawn(move || loop {
+        if let Some((sig, origin)) = wait(&set) {
+            handler(sig, origin);
+        }
+    });
+    Ok(())
+}
+
+/// Wait for one of the signals in `set`
+#[cfg(target_os = "linux")]
+fn wait(set: &libc::sigset_t) -> Option<(c_int, Origin)> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let sig = unsafe { libc::sigwaitinfo(set, &mut info) };
+    if sig == -1 {
+        return None;
+    }
+    let origin = if info.si_code == libc::SI_KERNEL {
+        Origin::Kernel
+    } else {
+        Origin::Process
+    };
+    Some((sig, origin))
+}
+
+/// Wait for one of the signals in `set`. Without sigwaitinfo() we can't ask who
+/// sent it, so assume the usual: SIGINT and SIGQUIT come from the keyboard.
+#[cfg(not(target_os = "linux"))]
+fn wait(set: &libc::sigset_t) -> Option<(c_int, Origin)> {
+    let mut sig: c_int = 0;
+    if unsafe { libc::sigwait(set, &mut sig) } != 0 {
+        return None;
+    }
+    let origin = match sig {
+        libc::SIGINT | libc::SIGQUIT => Origin::Kernel,
+        _ => Origin::Process,
+    };
+    Some((sig, origin))
+}
+
+/// Give a child we're about to spawn a clean signal mask. It would otherwise
+/// inherit ours, with everything we `block`ed still blocked.
+pub fn reset_mask(command: &mut Command) {
+    unsafe {
+        command.pre_exec(|| {
+            let set = sigset(&[]);
+            if libc::sigprocmask(libc::SIG_SETMASK, &set, ptr::null_mut()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Send `sig` to `pid`, or to its whole process group when it leads one
+pub fn send(pid: libc::pid_t, sig: c_int, whole_group: bool) {
+    let target = if whole_group { -pid } else { pid };
+    unsafe {
+        libc::kill(target, sig);
+    }
+}
+
+/// Exit the way the child did, so whoever started us sees the real cause:
+/// with its exit code, or by the same signal that killed it.
+pub fn exit_like(status: ExitStatus) -> ! {
+    let Some(sig) = status.signal() else {
+        std::process::exit(status.code().unwrap_or(1));
+    };
+
+    // Signals that would make us dump core too are reported the way shells
+    // do it, as 128+N; for the rest we die by the same signal
+    let dumps_core = matches!(
+        sig,
+        libc::SIGQUIT
+            | libc::SIGILL
+            | libc::SIGTRAP
+            | libc::SIGABRT
+            | libc::SIGBUS
+            | libc::SIGFPE
+            | libc::SIGSEGV
+            | libc::SIGSYS
+            | libc::SIGXCPU
+            | libc::SIGXFSZ
+    );
+    if !dumps_core {
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            let set = sigset(&[sig]);
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+            libc::raise(sig);
+        }
+    }
+    std::process::exit(128 + sig);
+}
+
+/// Conventional name of a signal, e.g. 11 -> "SIGSEGV"
+pub fn name(sig: c_int) -> String {
+    let name = match sig {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return format!("SIG{sig}"),
+    };
+    name.to_string()
+}
+
+/// Build a `sigset_t` holding exactly `sigs`.
+fn sigset(sigs: &[c_int]) -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &s in sigs {
+            libc::sigaddset(&mut set, s);
+        }
+        set
+    }
+}
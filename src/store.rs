@@ -0,0 +1,274 @@
+// src/store.rs
+
+// --------------------------------------------------------------------------------
+// The log directory as a collection of recorded runs. A run is identified by the
+// timestamp its files are named after ("20250712-153045.123"), and consists of a
+// log file plus its metadata sidecar.
+// --------------------------------------------------------------------------------
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use clap::Args;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{logfile, meta, meta::RunMeta, units};
+
+/// Format of the timestamp that run ids (and file names) are built from
+pub const ID_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+
+/// One recorded run
+pub struct Run {
+    /// e.g. "20250712-153045.123"
+    pub id: String,
+    /// Path of the log file
+    pub log: PathBuf,
+    /// Size of the log file (and its plain-text copy, if any) in bytes
+    pub size: u64,
+    /// The metadata sidecar, if there is one (and it parsed)
+    pub meta: Option<RunMeta>,
+}
+
+impl Run {
+    /// When the run started: from its metadata, or failing that its id
+    pub fn start(&self) -> Option<DateTime<Local>> {
+        if let Some(meta) = &self.meta {
+            return Some(meta.start);
+        }
+        let naive = NaiveDateTime::parse_from_str(&self.id, ID_FORMAT).ok()?;
+        Local.from_local_datetime(&naive).earliest()
+    }
+
+    /// Is the run pinned, i.e. exempt from pruning?
+    pub fn pinned(&self) -> bool {
+        self.meta.as_ref().is_some_and(|m| m.pinned)
+    }
+
+    /// Delete the run's files
+    pub fn remove(&self) {
+        remove_files(&self.log);
+    }
+}
+
+/// Delete the files of the run whose log is `log`. Errors are ignored: a
+/// half-deleted run is picked up again (or skipped) next time.
+pub fn remove_files(log: &Path) {
+    let _ = fs::remove_file(log);
+    let _ = fs::remove_file(logfile::plain_copy(log));
+    let _ = fs::remove_file(meta::sidecar(log));
+}
+
+/// Pick one run out of `runs` (oldest first) by reference:
+///  - nothing, "last" or "-1": the most recent run
+///  - "-N": the N-th most recent run
+///  - anything else: a run id, or an unambiguous prefix of one ("20250712-1530")
+pub fn resolve<'a>(runs: &'a [Run], reference: Option<&str>) -> io::Result<&'a Run> {
+    let not_found = |msg: String| io::Error::new(io::ErrorKind::NotFound, msg);
+
+    let back = match reference {
+        None | Some("last") => Some(1),
+        Some(r) => r.strip_prefix('-').and_then(|n| n.parse::<usize>().ok()),
+    };
+    if let Some(n) = back {
+        return n
+            .checked_sub(1)
+            .and_then(|i| runs.iter().rev().nth(i))
+            .ok_or_else(|| match runs.len() {
+                0 => not_found("no recorded runs".to_string()),
+                len => not_found(format!("only {len} matching run(s) recorded")),
+            });
+    }
+
+    let reference = reference.unwrap_or_default();
+    let mut matches = runs.iter().filter(|run| run.id.starts_with(reference));
+    match (matches.next(), matches.next()) {
+        (Some(run), None) => Ok(run),
+        (None, _) => Err(not_found(format!("no run matches {reference:?}"))),
+        (Some(_), Some(_)) => {
+            let exact = runs.iter().find(|run| run.id == reference);
+            exact.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{reference:?} matches more than one run; give more of the id"),
+                )
+            })
+        }
+    }
+}
+
+/// All runs in `dir`, oldest first. A missing directory simply has no runs.
+pub fn runs(dir: &Path) -> io::Result<Vec<Run>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut runs: Vec<Run> = entries
+        .filter_map(Result::ok)
+        .filter(|e| logfile::is_log(&e.path()))
+        .filter_map(|e| {
+            let log = e.path();
+            let id = logfile::run_id(&log)?;
+            let plain_size = fs::metadata(logfile::plain_copy(&log)).map_or(0, |m| m.len());
+            let size = e.metadata().map(|m| m.len()).unwrap_or(0) + plain_size;
+            let meta = RunMeta::read(&meta::sidecar(&log)).ok();
+            Some(Run {
+                id,
+                log,
+                size,
+                meta,
+            })
+        })
+        .collect();
+
+    // Ids are timestamps, so sorting by id is chronological
+    runs.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(runs)
+}
+
+/// Which exit statuses `--status` should let through
+#[derive(Clone, Debug)]
+pub enum StatusFilter {
+    /// Exited with 0
+    Ok,
+    /// Exited non-zero, or was killed by a signal
+    Failed,
+    /// Exited with exactly this code
+    Code(i32),
+}
+
+impl FromStr for StatusFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ok" | "success" => Ok(StatusFilter::Ok),
+            "failed" | "fail" => Ok(StatusFilter::Failed),
+            _ => s.parse().map(StatusFilter::Code).map_err(|_| {
+                format!("invalid status {s:?} (expected ok, failed, or an exit code)")
+            }),
+        }
+    }
+}
+
+/// Options for narrowing down which runs a subcommand looks at
+#[derive(Args, Debug, Default)]
+pub struct RunFilter {
+    /// Only runs of this program (compared against the command's basename)
+    #[clap(long = "cmd", value_name = "PROG")]
+    pub program: Option<String>,
+
+    /// Only runs that ended like this: `ok`, `failed`, or an exit code
+    #[clap(long, value_name = "STATUS")]
+    pub status: Option<StatusFilter>,
+
+    /// Only runs started at or after this time (YYYY-MM-DD, "YYYY-MM-DD HH:MM", or an age like 2h)
+    #[clap(long, value_name = "WHEN", value_parser = units::parse_when)]
+    pub since: Option<DateTime<Local>>,
+
+    /// Only runs started before this time (same formats as --since)
+    #[clap(long, value_name = "WHEN", value_parser = units::parse_when)]
+    pub until: Option<DateTime<Local>>,
+
+    /// Only pinned runs
+    #[clap(long)]
+    pub pinned: bool,
+}
+
+impl RunFilter {
+    /// Does `run` pass every filter that was given?
+    /// Runs without metadata can only pass the time filters.
+    pub fn matches(&self, run: &Run) -> bool {
+        if let Some(program) = &self.program {
+            if run.meta.as_ref().map(RunMeta::program) != Some(program.as_str()) {
+                return false;
+            }
+        }
+        if self.pinned && !run.pinned() {
+            return false;
+        }
+        if let Some(status) = &self.status {
+            let Some(meta) = &run.meta else {
+                return false;
+            };
+            let ok = match status {
+                StatusFilter::Ok => meta.exit_code == Some(0),
+                StatusFilter::Failed => meta.failed() == Some(true),
+                StatusFilter::Code(code) => meta.exit_code == Some(*code),
+            };
+            if !ok {
+                return false;
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(start) = run.start() else {
+                return false;
+            };
+            if self.since.is_some_and(|since| start < since)
+                || self.until.is_some_and(|until| start >= until)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs with these ids and no files behind them
+    fn runs_with_ids(ids: &[&str]) -> Vec<Run> {
+        let run = |id: &&str| Run {
+            id: id.to_string(),
+            log: PathBuf::from(format!("{id}.log")),
+            size: 0,
+            meta: None,
+        };
+        ids.iter().map(run).collect()
+    }
+
+    #[test]
+    fn counting_back_from_the_last_run() {
+        let runs = runs_with_ids(&[
+            "20250712-153045.123",
+            "20250712-160000.000",
+            "20250713-090000.000",
+        ]);
+        let id = |reference| {
+            let run = resolve(&runs, reference).map_err(|e| e.to_string())?;
+            Ok(run.id.as_str())
+        };
+
+        assert_eq!(id(None), Ok("20250713-090000.000"));
+        assert_eq!(id(Some("last")), Ok("20250713-090000.000"));
+        assert_eq!(id(Some("-1")), Ok("20250713-090000.000"));
+        assert_eq!(id(Some("-3")), Ok("20250712-153045.123"));
+        assert_eq!(id(Some("-4")), Err("only 3 matching run(s) recorded".to_string()));
+        assert!(id(Some("-0")).is_err());
+        assert!(resolve(&[], None).is_err_and(|e| e.to_string() == "no recorded runs"));
+    }
+
+    #[test]
+    fn picking_a_run_by_id_prefix() {
+        let runs = runs_with_ids(&[
+            "20250712-153045.123",
+            "20250712-160000.000",
+            "20250712-160000.0001",
+        ]);
+        let id = |reference| resolve(&runs, Some(reference)).map(|run| run.id.as_str());
+
+        assert_eq!(id("20250712-15").unwrap(), "20250712-153045.123");
+        assert_eq!(id("20250712-153045.123").unwrap(), "20250712-153045.123");
+        assert_eq!(id("2026").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // More than one starts with it: only an exact id will do
+        let ambiguous = id("20250712-1").unwrap_err();
+        assert_eq!(ambiguous.kind(), io::ErrorKind::InvalidInput);
+        assert!(ambiguous.to_string().contains("matches more than one run"));
+        assert_eq!(id("20250712-160000.000").unwrap(), "20250712-160000.000");
+    }
+}
@@ -0,0 +1,125 @@
+// src/tee.rs
+
+// --------------------------------------------------------------------------------
+// The tee-threads: copy a child's output to our terminal and into the log.
+// --------------------------------------------------------------------------------
+use std::{
+    io::{self, Read, Write},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    logfile::{SharedLog, Stream},
+    redact::{Redactor, Rules},
+};
+
+/// How many bytes we pull from a child pipe per `read` call.
+const TEE_CHUNK: usize = 64 * 1024;
+
+/// Spawn a thread that "tees" everything from `pipe` into both
+/// 1) the real terminal (stdout or stderr), and
+/// 2) our logfile (`log`), tagged with the stream it came from.
+///
+/// Bytes are forwarded in chunks exactly as `read` hands them to us, so
+/// non-UTF-8 output, prompts without a trailing newline and multi-megabyte
+/// single lines all pass through untouched, with memory bounded by `TEE_CHUNK`.
+///
+/// With `redact` rules, secrets are blanked out of what goes into the log (and
+/// only there). That works line by line, so the log lags behind the terminal
+/// by up to a line.
+///
+/// Take `pipe` as any `impl Read + Send + 'static`.
+pub fn spawn_tee<P>(
+    mut pipe: P,
+    log: SharedLog,
+    stream: Stream,
+    redact: Option<Rules>,
+) -> JoinHandle<()>
+where
+    P: Read + Send + 'static,
+{
+    // Spawn a thread that:
+    //      - loops on pipe.read() until EOF
+    //      - writes each chunk to the real terminal AND to my logfile
+    thread::spawn(move || {
+        let mut buf = vec![0u8; TEE_CHUNK];
+        // Once the log fails we stop writing to it, but keep draining the pipe
+        // so the child never blocks on a full pipe. A terminal that's gone away
+        // (e.g. `stash -- cmd | head`) ends the tee instead: closing the pipe
+        // gets the child its SIGPIPE, as it would without us.
+        let mut term_ok = true;
+        let mut log_ok = true;
+        let mut redactor = redact.map(Redactor::new);
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // A PTY master reports EIO once the slave side has been closed
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) => {
+                    eprintln!("stash: error reading child output: {}", e);
+                    break;
+                }
+            };
+            let chunk = &buf[..n];
+
+            // a) Write to the terminal, flushing so partial lines show up immediately
+            let mut broken_pipe = false;
+            if term_ok {
+                if let Err(e) = write_term(stream, chunk) {
+                    term_ok = false;
+                    broken_pipe = e.kind() == io::ErrorKind::BrokenPipe;
+                }
+            }
+            // b) append to the logfile, minus any secrets
+            if log_ok {
+                let redacted;
+                let chunk = match &mut redactor {
+                    Some(redactor) => {
+                        redacted = redactor.push(chunk);
+                        &redacted[..]
+                    }
+                    None => chunk,
+                };
+                log_ok = write_log(&log, stream, chunk);
+            }
+            if broken_pipe {
+                break;
+            }
+        }
+        drop(pipe);
+
+        // A last line without a newline is still waiting in the redactor
+        if let Some(redactor) = &mut redactor {
+            if log_ok {
+                write_log(&log, stream, &redactor.finish());
+            }
+        }
+    })
+}
+
+/// Append one chunk to the log, complaining if that fails; returns whether it worked.
+pub fn write_log(log: &SharedLog, stream: Stream, chunk: &[u8]) -> bool {
+    if chunk.is_empty() {
+        return true;
+    }
+    if let Err(e) = log.lock().unwrap().write_chunk(stream, chunk) {
+        eprintln!("stash: failed to write log: {}", e);
+        return false;
+    }
+    true
+}
+
+/// Write one chunk to our own stdout or stderr and flush it.
+fn write_term(stream: Stream, chunk: &[u8]) -> io::Result<()> {
+    if stream == Stream::Stderr {
+        let mut err = io::stderr().lock();
+        err.write_all(chunk)?;
+        err.flush()
+    } else {
+        let mut out = io::stdout().lock();
+        out.write_all(chunk)?;
+        out.flush()
+    }
+}
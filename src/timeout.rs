@@ -0,0 +1,115 @@
+// src/timeout.rs
+
+// --------------------------------------------------------------------------------
+// `--timeout`: stopping a command that runs for too long, the way timeout(1)
+// does. It's asked to stop with SIGTERM, and killed with SIGKILL if it hasn't
+// after a grace period (`--kill-after`).
+//
+// The signals go to the command's whole process group, so whatever it started
+// stops too. On a PTY it leads one anyway; with pipes it gets one of its own,
+// in the background like timeout(1) puts it. `--foreground` leaves it in ours,
+// where it can read from the terminal and be stopped with Ctrl-Z along with
+// us, and only the command itself is timed out.
+// --------------------------------------------------------------------------------
+use chrono::Duration;
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    logfile::{SharedLog, Stream},
+    signals, tee, units,
+};
+
+/// What we exit with when the command timed out, like timeout(1)
+pub const EXIT_CODE: i32 = 124;
+
+/// How long a command gets to stop after SIGTERM, unless told otherwise
+pub const DEFAULT_KILL_AFTER: Duration = Duration::seconds(10);
+
+/// Keeps an eye on the clock for a running command
+pub struct Watchdog {
+    done: mpsc::Sender<()>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    /// Stand down, now that the command has exited. Returns whether it ran
+    /// out of time.
+    pub fn disarm(self) -> bool {
+        let _ = self.done.send(());
+        self.timed_out.load(Ordering::SeqCst)
+    }
+}
+
+/// Spawn a thread that stops `pid` (with `whole_group`, the process group it
+/// leads) once it's been running for `limit`: SIGTERM first, then SIGKILL if
+/// it's still there after `kill_after`. What it does is noted on our stderr
+/// and in `log`.
+pub fn spawn_watchdog(
+    pid: u32,
+    limit: Duration,
+    kill_after: Duration,
+    whole_group: bool,
+    log: SharedLog,
+) -> Watchdog {
+    let (done, done_rx) = mpsc::channel();
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let flag = timed_out.clone();
+    let pid = pid as libc::pid_t;
+    thread::spawn(move || {
+        // 1. Wait it out, unless the command is done first
+        let wait = |d: Duration| done_rx.recv_timeout(d.to_std().unwrap_or_default());
+        if wait(limit) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+
+        // 2. Ask it to stop
+        flag.store(true, Ordering::SeqCst);
+        note(
+            &log,
+            &format!("timed out after {}, sending SIGTERM", units::format_age(limit)),
+        );
+        signals::send(pid, libc::SIGTERM, whole_group);
+
+        // 3. Make it stop
+        if wait(kill_after) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+        note(
+            &log,
+            &format!("still running {} later, sending SIGKILL", units::format_age(kill_after)),
+        );
+        signals::send(pid, libc::SIGKILL, whole_group);
+    });
+    Watchdog { done, timed_out }
+}
+
+/// Say what we did, on the terminal and in the log (as stderr)
+fn note(log: &SharedLog, what: &str) {
+    let line = format!("stash: {what}\n");
+    eprint!("{line}");
+    tee::write_log(log, Stream::Stderr, line.as_bytes());
+}
+
+/// Have the child that `command` starts lead a process group of its own. It
+/// isn't handed the terminal: it would be stopped by Ctrl-Z there without our
+/// noticing, and we'd wait on it for good.
+pub fn own_group(command: &mut Command) {
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,97 @@
+// src/trust.rs
+
+// --------------------------------------------------------------------------------
+// `stash trust` / `stash untrust`: which project `.stash.toml` files we read.
+//
+// A project file comes with whatever repo we happen to be in, so it's only used
+// once approved, and only as long as it's unchanged: we remember the SHA-256 of
+// each approved file in $XDG_DATA_HOME/stash/trusted, one "<hash>  <path>" a line.
+// --------------------------------------------------------------------------------
+use clap::Args;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::config;
+
+#[derive(Args, Debug)]
+pub struct TrustArgs {
+    /// The `.stash.toml` to (dis)approve (default: those that apply in the
+    /// current directory)
+    #[clap(value_name = "FILE")]
+    file: Option<PathBuf>,
+}
+
+/// Is the project file at `path`, which holds `contents`, approved as it is?
+pub fn is_trusted(path: &Path, contents: &str) -> bool {
+    let Ok(path) = fs::canonicalize(path) else {
+        return false;
+    };
+    let entry = (hash(contents), path.to_string_lossy().into_owned());
+    load().contains(&entry)
+}
+
+/// Approve (or withdraw approval of) the file `args` names, or every project
+/// file found from the current directory
+pub fn set(args: &TrustArgs, trusted: bool) -> io::Result<()> {
+    // 1. Which files
+    let files = match &args.file {
+        Some(file) => vec![file.clone()],
+        None => config::project_files(&env::current_dir()?),
+    };
+    if files.is_empty() {
+        return Err(io::Error::other(format!(
+            "no {} here or up to the repository root",
+            config::PROJECT_FILE
+        )));
+    }
+
+    // 2. Drop what we had for them, then add them back as they are now
+    let mut entries = load();
+    for file in files {
+        let path = fs::canonicalize(&file)?;
+        let path_str = path.to_string_lossy().into_owned();
+        entries.retain(|(_, p)| *p != path_str);
+        if trusted {
+            entries.push((hash(&fs::read_to_string(&path)?), path_str));
+            println!("trusted {}", path.display());
+        } else {
+            println!("untrusted {}", path.display());
+        }
+    }
+
+    // 3. Save
+    let store = store_path()?;
+    fs::create_dir_all(store.parent().unwrap())?;
+    let text: String = entries
+        .iter()
+        .map(|(hash, path)| format!("{hash}  {path}\n"))
+        .collect();
+    fs::write(store, text)
+}
+
+/// The approved files, as (hash, path) pairs
+fn load() -> Vec<(String, String)> {
+    let Ok(text) = store_path().and_then(fs::read_to_string) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, path)| (hash.to_string(), path.to_string()))
+        .collect()
+}
+
+fn store_path() -> io::Result<PathBuf> {
+    let dir = dirs::data_dir().ok_or_else(|| io::Error::other("no data directory"))?;
+    Ok(dir.join("stash").join("trusted"))
+}
+
+/// Hex SHA-256 of a file's contents
+fn hash(contents: &str) -> String {
+    Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
@@ -0,0 +1,294 @@
+// src/units.rs
+
+// --------------------------------------------------------------------------------
+// Parsing and pretty-printing of the human-friendly values we accept and show:
+// durations ("14d", "1h30m"), points in time ("2025-07-12", "2h" ago) and sizes.
+// --------------------------------------------------------------------------------
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{de, Deserialize, Deserializer};
+
+/// Parse a duration such as "90s", "15m", "12h", "14d", "2w" or "1h30m".
+/// A bare number is taken as seconds. Negative durations are refused.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if s.starts_with('-') {
+        return Err(format!("duration can't be negative: {s}"));
+    }
+    if let Ok(secs) = s.parse::<i64>() {
+        return Duration::try_seconds(secs).ok_or_else(|| format!("duration out of range: {s}"));
+    }
+
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let n: i64 = digits
+            .parse()
+            .map_err(|_| format!("invalid duration {s:?} (expected e.g. 30s, 15m, 12h, 14d)"))?;
+        digits.clear();
+        let secs_per_unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => {
+                return Err(format!(
+                    "unknown unit {c:?} in duration {s:?} (use s, m, h, d or w)"
+                ))
+            }
+        };
+        let part = n
+            .checked_mul(secs_per_unit)
+            .and_then(Duration::try_seconds)
+            .ok_or_else(|| format!("duration out of range: {s}"))?;
+        total += part;
+    }
+    if !digits.is_empty() {
+        return Err(format!("missing unit after {digits:?} in duration {s:?}"));
+    }
+    Ok(total)
+}
+
+/// Serde helper for optional duration fields in stash.toml, e.g. `max_age = "14d"`
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_duration(&s).map(Some).map_err(de::Error::custom)
+}
+
+/// Like `parse_duration`, for the waits where nothing at all makes no sense
+/// (`timeout`, `kill_after`)
+pub fn parse_nonzero_duration(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        d if d.is_zero() => Err(format!("duration must be more than zero: {}", s.trim())),
+        d => Ok(d),
+    }
+}
+
+/// Serde helper for `parse_nonzero_duration` fields, e.g. `timeout = "15m"`
+pub fn deserialize_nonzero_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_nonzero_duration(&s).map(Some).map_err(de::Error::custom)
+}
+
+/// Parse a size such as "512", "64K", "100MiB", "2GiB" or "1.5G". K/M/G/T and
+/// KiB/MiB/GiB/TiB are powers of 1024; KB/MB/GB/TB are powers of 1000.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {s:?} (expected e.g. 500M or 2GiB)"))?;
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "k" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "T" | "TiB" => 1 << 40,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        other => {
+            return Err(format!(
+                "unknown unit {other:?} in size {s:?} (use K, M, G or T)"
+            ))
+        }
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Serde helper for optional size fields in stash.toml: `max_total = "2GiB"`,
+/// or a plain number of bytes
+pub fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(Some(bytes)),
+        Size::Text(s) => parse_size(&s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// Parse a point in time: a date ("2025-07-12"), a date and time
+/// ("2025-07-12 15:30" / "2025-07-12T15:30:45"), or a duration meaning
+/// "that long ago" ("2h", "3d").
+pub fn parse_when(s: &str) -> Result<DateTime<Local>, String> {
+    let s = s.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return local(date.and_hms_opt(0, 0, 0).unwrap(), s);
+    }
+    for fmt in [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, fmt) {
+            return local(dt, s);
+        }
+    }
+    match parse_duration(s) {
+        Ok(ago) => Ok(Local::now() - ago),
+        Err(_) => Err(format!(
+            "invalid time {s:?} (expected YYYY-MM-DD, \"YYYY-MM-DD HH:MM\", or an age like 2h)"
+        )),
+    }
+}
+
+fn local(dt: NaiveDateTime, s: &str) -> Result<DateTime<Local>, String> {
+    Local
+        .from_local_datetime(&dt)
+        .earliest()
+        .ok_or_else(|| format!("{s:?} doesn't exist in the local time zone"))
+}
+
+/// Render a run time compactly: "850ms", "12.3s", "4m05s", "2h13m"
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else if secs < 60 {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    } else if secs < 60 * 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+/// Render an age the way `parse_duration` reads it, in the largest unit that
+/// fits exactly: "14d", "90m"
+pub fn format_age(age: Duration) -> String {
+    let secs = age.num_seconds();
+    let unit = [
+        ("w", 7 * 24 * 3600),
+        ("d", 24 * 3600),
+        ("h", 3600),
+        ("m", 60),
+    ]
+    .into_iter()
+    .find(|(_, size)| secs != 0 && secs % size == 0);
+    match unit {
+        Some((unit, size)) => format!("{}{unit}", secs / size),
+        None => format!("{secs}s"),
+    }
+}
+
+/// Render a byte count the way `parse_size` reads it, in the largest binary
+/// unit that fits exactly: "2GiB", "1500B"
+pub fn format_exact_size(bytes: u64) -> String {
+    let unit = [
+        ("TiB", 1u64 << 40),
+        ("GiB", 1 << 30),
+        ("MiB", 1 << 20),
+        ("KiB", 1 << 10),
+    ]
+    .into_iter()
+    .find(|(_, size)| bytes != 0 && bytes.is_multiple_of(*size));
+    match unit {
+        Some((unit, size)) => format!("{}{unit}", bytes / size),
+        None => format!("{bytes}B"),
+    }
+}
+
+/// Render a byte count with binary units: "812 B", "4.1 KiB", "2.0 GiB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        for (text, secs) in [
+            ("90s", 90),
+            ("15m", 15 * 60),
+            ("1h30m", 90 * 60),
+            ("14d", 14 * 24 * 3600),
+            ("2w", 14 * 24 * 3600),
+            ("45", 45),
+            (" 0 ", 0),
+        ] {
+            assert_eq!(parse_duration(text), Ok(Duration::seconds(secs)), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn bad_durations() {
+        for text in ["", "-60", "-1s", "-1h30m", "5x", "1h30", "h", "1.5h"] {
+            assert!(parse_duration(text).is_err(), "{text:?} should be refused");
+        }
+    }
+
+    #[test]
+    fn nonzero_durations() {
+        assert_eq!(parse_nonzero_duration("1s"), Ok(Duration::seconds(1)));
+        assert!(parse_nonzero_duration("0").is_err());
+        assert!(parse_nonzero_duration("0s0m").is_err());
+        assert!(parse_nonzero_duration("-5").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        for (text, bytes) in [
+            ("512", 512),
+            ("512B", 512),
+            ("64K", 64 << 10),
+            ("100MiB", 100 << 20),
+            ("2GiB", 2 << 30),
+            ("1.5G", 3 << 29),
+            ("2GB", 2_000_000_000),
+            ("1 TB", 1_000_000_000_000),
+        ] {
+            assert_eq!(parse_size(text), Ok(bytes), "{text:?}");
+        }
+        for text in ["", "-5", "-5M", "5Q", "M", "1.2.3K"] {
+            assert!(parse_size(text).is_err(), "{text:?} should be refused");
+        }
+    }
+
+    #[test]
+    fn formatted_values_parse_back() {
+        for secs in [1, 59, 60, 90, 3600, 86400, 7 * 86400, 8 * 86400 + 1] {
+            let age = Duration::seconds(secs);
+            assert_eq!(parse_duration(&format_age(age)), Ok(age));
+        }
+        for bytes in [0, 1, 1500, 1 << 10, 3 << 20, 2 << 30] {
+            assert_eq!(parse_size(&format_exact_size(bytes)), Ok(bytes));
+        }
+    }
+}